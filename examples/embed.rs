@@ -3,10 +3,10 @@ use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::PathBuf;
-use wasi_common::preopen_dir;
 use wasmtime_embed::{
-    create_wasi, instantiate, instantiate_in_context, wasm_export_impl, wasm_import_wrapper,
-    ContextToken, ImportSet, InstanceToken, RuntimeValue, WasmExport,
+    instantiate, instantiate_in_context, run_command, wasm_export_impl, wasm_import_wrapper,
+    live_counts, ContextToken, Func, FuncType, HostModuleBuilder, ImportSet, Linker, RuntimeValue,
+    Store, ValType, WasiConfig, WasmExport,
 };
 
 use wasmtime_embed_macro::{wasm_export, wasm_import};
@@ -60,6 +60,10 @@ fn main() -> Result<(), Error> {
     let res = gcd.invoke(&[RuntimeValue::I32(6), RuntimeValue::I32(27)])?;
     println!("gcd(6, 27) = {}", res[0]);
 
+    // Typed call, traps are reported as errors.
+    let gcd = instance.get_typed_func::<(u32, u32), u32>("gcd")?;
+    println!("gcd(6, 27) = {} (via TypedFunc)", gcd.call((6, 27))?);
+
     // InstanceHandle for wrapped Rust struct (TestCallback trait)
     let callback_host = TestCallbackC::new();
     let l0 = wasm_import_wrapper!(callback_host for <TestCallbackC as TestCallback>);
@@ -69,6 +73,47 @@ fn main() -> Result<(), Error> {
     let l1_wasm = read_binary("l1.wasm")?;
     let mut l1_imports = HashMap::new();
     l1_imports.insert(String::from("test"), ImportSet::InstanceExports(l0));
+    l1_imports.insert(String::from("gcd"), ImportSet::InstanceExports(instance.clone()));
+    let _l1 = instantiate(&l1_wasm, l1_imports)?;
+
+    // The same, with the imports resolved by name.
+    let callback_host = TestCallbackC::new();
+    let callbacks = wasm_import_wrapper!(callback_host for <TestCallbackC as TestCallback>);
+    let mut linker = Linker::new(ContextToken::create());
+    linker
+        .instance("test", callbacks)?
+        .instance("gcd", instance.clone())?;
+    let _l1 = linker.instantiate(&l1_wasm)?;
+
+    // Modules can also be given in the text format.
+    let add = instantiate(
+        br#"(module
+          (func (export "add") (param i32 i32) (result i32)
+            local.get 0
+            local.get 1
+            i32.add))"#,
+        HashMap::new(),
+    )?;
+    let add = add.get_typed_func::<(u32, u32), u32>("add")?;
+    println!("add(2, 3) = {} (from WAT)", add.call((2, 3))?);
+
+    // The same "test" import, but built from a closure (no trait needed).
+    let l0_closure = HostModuleBuilder::new()
+        .wrap("callback", |c: u32| println!("callback (from closure): {}", c))
+        .build();
+    let mut l1_imports = HashMap::new();
+    l1_imports.insert(String::from("test"), ImportSet::InstanceExports(l0_closure));
+    l1_imports.insert(String::from("gcd"), ImportSet::InstanceExports(instance.clone()));
+    let _l1 = instantiate(&l1_wasm, l1_imports)?;
+
+    // ... or from a signature known only at runtime.
+    let callback = Func::new(FuncType::new(vec![ValType::I32], vec![]), |args| {
+        println!("callback (dynamic): {}", args[0]);
+        Ok(vec![])
+    });
+    let l0_dynamic = HostModuleBuilder::new().func("callback", callback).build();
+    let mut l1_imports = HashMap::new();
+    l1_imports.insert(String::from("test"), ImportSet::InstanceExports(l0_dynamic));
     l1_imports.insert(String::from("gcd"), ImportSet::InstanceExports(instance));
     let _l1 = instantiate(&l1_wasm, l1_imports)?;
 
@@ -76,7 +121,11 @@ fn main() -> Result<(), Error> {
     let context = ContextToken::create();
 
     // Instantiate WASI (as InstanceHandle)
-    let wasi = build_wasi(&context);
+    let wasi = WasiConfig::new()
+        .arg("test")
+        .preopen_dir(".")
+        .map_dir("/tmp", "/var/tmp")
+        .build(&context)?;
     // Instantiate hello.wasm with wasi as import (in the same context).
     let hello_wasm = read_binary("hello.wasm")?;
     let mut hello_imports = HashMap::new();
@@ -93,40 +142,35 @@ fn main() -> Result<(), Error> {
         println!("data: {:?}", data);
     }
 
-    Ok(())
-}
-
-fn build_wasi(context: &ContextToken) -> InstanceToken {
-    let preopen_dirs = compute_preopen_dirs(
-        &vec![String::from(".")],
-        &vec![String::from("/tmp:/var/tmp")],
+    // Run hello.wasm as a command, capturing its output.
+    let mut wasi = WasiConfig::new();
+    let stdout = wasi.arg("hello").capture_stdout()?;
+    let status = run_command(&hello_wasm, &mut wasi)?;
+    println!(
+        "hello exited with {:?}, output: {}",
+        status.code(),
+        String::from_utf8_lossy(&stdout.contents()?)
     );
-    let argv: Vec<String> = vec![String::from("test")];
-    let environ: Vec<(String, String)> = vec![];
-    create_wasi(context.clone(), &preopen_dirs, &argv, &environ)
-}
-
-fn compute_preopen_dirs(flag_dir: &[String], flag_mapdir: &[String]) -> Vec<(String, File)> {
-    let mut preopen_dirs = Vec::new();
-
-    for dir in flag_dir {
-        let preopen_dir = preopen_dir(dir).unwrap_or_else(|err| {
-            panic!("error while pre-opening directory {}: {}", dir, err);
-        });
-        preopen_dirs.push((dir.clone(), preopen_dir));
-    }
 
-    for mapdir in flag_mapdir {
-        let parts: Vec<&str> = mapdir.split(':').collect();
-        if parts.len() != 2 {
-            panic!("--mapdir argument must contain exactly one colon, separating a guest directory name and a host directory name");
-        }
-        let (key, value) = (parts[0], parts[1]);
-        let preopen_dir = preopen_dir(value).unwrap_or_else(|err| {
-            panic!("error while pre-opening directory {}: {}", value, err);
-        });
-        preopen_dirs.push((key.to_string(), preopen_dir));
-    }
+    // A store owns its instances, which are released by `clear`. Its data
+    // is available to the host functions called from its instances.
+    let mut store = Store::with_data(Vec::new());
+    let gcd = store.instantiate(&gcd_wasm, HashMap::new())?;
+    let test = store.add(
+        HostModuleBuilder::new()
+            .wrap_with_data("callback", |seen: &mut Vec<u32>, c: u32| seen.push(c))
+            .build(),
+    );
+    let mut l1_imports = HashMap::new();
+    l1_imports.insert(String::from("test"), ImportSet::InstanceExports(test));
+    l1_imports.insert(String::from("gcd"), ImportSet::InstanceExports(gcd));
+    let l1 = store.instantiate(&l1_wasm, l1_imports)?;
+    l1.get_typed_func::<(), ()>("main")?.call(())?;
+    println!("callbacks seen by the store: {:?}", *store.data());
+    println!("live in store: {:?}", live_counts());
+    drop(l1);
+    store.clear();
+    println!("live after clear: {:?}", live_counts());
 
-    preopen_dirs
+    Ok(())
 }
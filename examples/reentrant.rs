@@ -0,0 +1,137 @@
+use failure::Error;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use wasmtime_embed::{
+    instantiate_in_context, wasm_export_impl, wasm_import_wrapper, Config, ContextToken, Func,
+    FuncType, HostModuleBuilder, ImportSet, InstanceToken, RuntimeValue, Trap, TrapCode,
+    ValType, WasmExport,
+};
+
+use wasmtime_embed_macro::{wasm_export, wasm_import};
+
+// `walk(n)` asks the host to visit `n`, the host walks back into the guest
+// with `n - 1`, so `walk(n)` returns `n` after `n` nested calls.
+const GUEST: &str = r#"(module
+  (import "host" "visit" (func $visit (param i32) (result i32)))
+  (func (export "walk") (param i32) (result i32)
+    local.get 0
+    i32.eqz
+    if (result i32)
+      i32.const 0
+    else
+      local.get 0
+      call $visit
+      i32.const 1
+      i32.add
+    end))"#;
+
+#[wasm_export]
+trait Guest {
+    fn walk(&self, n: u32) -> u32;
+}
+
+#[wasm_import]
+trait Visitor {
+    fn visit(&self, n: u32) -> u32;
+}
+
+// The guest is instantiated after its import, so the host gets it later.
+type GuestCell = Rc<RefCell<Option<InstanceToken>>>;
+
+fn guest(cell: &GuestCell) -> InstanceToken {
+    // Clone the guest out, the cell must not stay borrowed while the guest
+    // runs and calls the host again.
+    cell.borrow().clone().expect("guest instance")
+}
+
+struct WalkBack {
+    guest: GuestCell,
+}
+
+impl Visitor for WalkBack {
+    fn visit(&self, n: u32) -> u32 {
+        let guest = guest(&self.guest);
+        wasm_export_impl!(guest as Guest).walk(n - 1)
+    }
+}
+
+fn instantiate_guest(host: InstanceToken, cell: &GuestCell, config: Config) -> Result<(), Error> {
+    let mut imports = HashMap::new();
+    imports.insert(String::from("host"), ImportSet::InstanceExports(host));
+    let context = ContextToken::with_config(config);
+    let instance = instantiate_in_context(GUEST.as_bytes(), imports, context)?;
+    *cell.borrow_mut() = Some(instance);
+    Ok(())
+}
+
+fn main() -> Result<(), Error> {
+    let mut config = Config::new();
+    config.max_call_depth(16);
+
+    // `#[wasm_import]` host calling back through a `#[wasm_export]` proxy.
+    let cell = GuestCell::default();
+    let walk_back = WalkBack {
+        guest: cell.clone(),
+    };
+    let host = wasm_import_wrapper!(walk_back for <WalkBack as Visitor>);
+    instantiate_guest(host, &cell, config.clone())?;
+    let walk = wasm_export_impl!((guest(&cell)) as Guest);
+    println!("walk(5) = {} (via wasm_import)", walk.walk(5));
+    // The proxies panic with the trap, e.g. of exceeding the call depth.
+    let payload = panic::catch_unwind(AssertUnwindSafe(|| walk.walk(100))).unwrap_err();
+    let trap = payload.downcast::<Trap>().expect("trap");
+    assert_eq!(trap.code(), TrapCode::StackOverflow);
+    println!("walk(100) failed: {} (via wasm_import)", trap);
+
+    // Dynamic host function calling back with `invoke`. Traps of the nested
+    // calls, e.g. exceeding the call depth, are passed on to the caller.
+    let cell = GuestCell::default();
+    let visit = {
+        let cell = cell.clone();
+        let ty = FuncType::new(vec![ValType::I32], vec![ValType::I32]);
+        Func::new(ty, move |args| {
+            let n = match args[0] {
+                RuntimeValue::I32(n) => n,
+                _ => unreachable!(),
+            };
+            let walk = guest(&cell).get_export("walk").expect("walk");
+            walk.invoke(&[RuntimeValue::I32(n - 1)]).map_err(|e| {
+                e.downcast::<Trap>()
+                    .unwrap_or_else(|e| Trap::new(e.to_string()))
+            })
+        })
+    };
+    let host = HostModuleBuilder::new().func("visit", visit).build();
+    instantiate_guest(host, &cell, config.clone())?;
+    let walk = guest(&cell).get_export("walk").expect("walk");
+    println!("walk(5) = {:?} (via invoke)", walk.invoke(&[RuntimeValue::I32(5)])?);
+    let error = walk.invoke(&[RuntimeValue::I32(100)]).unwrap_err();
+    let trap = error.downcast::<Trap>().expect("trap");
+    assert_eq!(trap.code(), TrapCode::StackOverflow);
+    println!("walk(100) failed: {} (via invoke)", trap);
+
+    // Closure calling back with a typed function.
+    let cell = GuestCell::default();
+    let visit = {
+        let cell = cell.clone();
+        move |n: u32| -> u32 {
+            let walk = guest(&cell).get_typed_func::<(u32,), u32>("walk");
+            match walk.and_then(|walk| walk.call((n - 1,))) {
+                Ok(result) => result,
+                Err(e) => {
+                    println!("nested walk({}) failed: {}", n - 1, e);
+                    0
+                }
+            }
+        }
+    };
+    let host = HostModuleBuilder::new().wrap("visit", visit).build();
+    instantiate_guest(host, &cell, config)?;
+    let walk = guest(&cell).get_typed_func::<(u32,), u32>("walk")?;
+    println!("walk(5) = {} (via TypedFunc)", walk.call((5,))?);
+    println!("walk(100) = {} (via TypedFunc)", walk.call((100,))?);
+
+    Ok(())
+}
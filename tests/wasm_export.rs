@@ -0,0 +1,89 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use wasmtime_embed::{
+    instantiate, instantiate_in_context, wasm_export_impl, Config, ContextToken,
+    HostModuleBuilder, ImportSet, InstanceToken, Trap, TrapCode, WasmExport,
+};
+use wasmtime_embed_macro::wasm_export;
+
+#[wasm_export]
+trait Guest {
+    fn add(&self, a: u32, b: u32) -> u32;
+    fn fail(&self);
+}
+
+const GUEST: &str = r#"(module
+  (func (export "add") (param i32 i32) (result i32)
+    local.get 0
+    local.get 1
+    i32.add)
+  (func (export "fail") unreachable))"#;
+
+#[wasm_export]
+trait Walker {
+    fn walk(&self, n: u32) -> u32;
+}
+
+// `walk(n)` calls the host, which calls `walk(n - 1)` through a proxy.
+const WALKER: &str = r#"(module
+  (import "host" "visit" (func $visit (param i32) (result i32)))
+  (func (export "walk") (param i32) (result i32)
+    local.get 0
+    i32.eqz
+    if (result i32)
+      i32.const 0
+    else
+      local.get 0
+      call $visit
+      i32.const 1
+      i32.add
+    end))"#;
+
+fn panic_trap<R>(f: impl FnOnce() -> R) -> Trap {
+    let payload = panic::catch_unwind(AssertUnwindSafe(f)).expect_err("panic");
+    *payload.downcast::<Trap>().expect("Trap")
+}
+
+#[test]
+fn proxy_calls_export() {
+    let instance = instantiate(GUEST.as_bytes(), HashMap::new()).unwrap();
+    assert_eq!(wasm_export_impl!(instance as Guest).add(2, 3), 5);
+}
+
+#[test]
+fn proxy_panics_with_trap() {
+    let instance = instantiate(GUEST.as_bytes(), HashMap::new()).unwrap();
+    let guest = wasm_export_impl!(instance as Guest);
+    let trap = panic_trap(|| guest.fail());
+    assert_eq!(trap.code(), TrapCode::Unreachable);
+    // The instance is still usable after the trap.
+    assert_eq!(guest.add(1, 1), 2);
+}
+
+#[test]
+fn nested_proxy_calls_are_depth_limited() {
+    let cell: Rc<RefCell<Option<InstanceToken>>> = Rc::default();
+    let host = {
+        let cell = cell.clone();
+        HostModuleBuilder::new()
+            .wrap("visit", move |n: u32| -> u32 {
+                let walker = cell.borrow().clone().expect("walker");
+                wasm_export_impl!(walker as Walker).walk(n - 1)
+            })
+            .build()
+    };
+    let mut imports = HashMap::new();
+    imports.insert(String::from("host"), ImportSet::InstanceExports(host));
+    let mut config = Config::new();
+    config.max_call_depth(8);
+    let context = ContextToken::with_config(config);
+    let instance = instantiate_in_context(WALKER.as_bytes(), imports, context).unwrap();
+    *cell.borrow_mut() = Some(instance.clone());
+
+    let walker = wasm_export_impl!(instance as Walker);
+    assert_eq!(walker.walk(5), 5);
+    let trap = panic_trap(|| walker.walk(100));
+    assert_eq!(trap.code(), TrapCode::StackOverflow);
+}
@@ -16,10 +16,10 @@ use syn::{
 
 fn convert_type(ty: &Type) -> TokenStream2 {
     match ty {
-        Type::Path(p) if p.path.is_ident("u32") => quote! { ir::types::I32 },
-        Type::Path(p) if p.path.is_ident("u64") => quote! { ir::types::I64 },
-        Type::Path(p) if p.path.is_ident("f32") => quote! { ir::types::F32 },
-        Type::Path(p) if p.path.is_ident("f64") => quote! { ir::types::F64 },
+        Type::Path(p) if p.path.is_ident("u32") => quote! { ValType::I32 },
+        Type::Path(p) if p.path.is_ident("u64") => quote! { ValType::I64 },
+        Type::Path(p) if p.path.is_ident("f32") => quote! { ValType::F32 },
+        Type::Path(p) if p.path.is_ident("f64") => quote! { ValType::F64 },
         _ => panic!("unsupported type"),
     }
 }
@@ -39,9 +39,6 @@ fn convert_method_sig(sig: &MethodSig) -> (TokenStream2, TokenStream2, TokenStre
         match param {
             FnArg::SelfRef(_) => {
                 ty_args.extend(quote! { *mut VMContext });
-                params.extend(quote! {
-                    ir::AbiParam::special(ir::types::I64, ir::ArgumentPurpose::VMContext)
-                });
             }
             FnArg::Captured(ArgCaptured {
                 pat:
@@ -56,7 +53,7 @@ fn convert_method_sig(sig: &MethodSig) -> (TokenStream2, TokenStream2, TokenStre
             }) => {
                 ty_args.extend(quote! { , #ty });
                 let param_type = convert_type(ty);
-                params.extend(quote! { , ir::AbiParam::new(#param_type) });
+                params.extend(quote! { #param_type, });
                 call_passthru_params.extend(quote! {, #ident });
             }
             _ => panic!("unsupported param type"),
@@ -70,9 +67,7 @@ fn convert_method_sig(sig: &MethodSig) -> (TokenStream2, TokenStream2, TokenStre
         ReturnType::Type(_, ref ty) => {
             ty_ret = quote! { -> #ty };
             let return_type = convert_type(ty);
-            returns.extend(quote! {
-                ir::AbiParam::new(#return_type)
-            });
+            returns.extend(quote! { #return_type });
         }
     }
 
@@ -100,11 +95,7 @@ fn convert_method(
     metas.extend(quote! {
         let #field_name = instance.get_callable_export(
             #wasm_name,
-            ir::Signature {
-                params: vec![#params],
-                returns: vec![#returns],
-                call_conv: isa::CallConv::SystemV,
-            }
+            &FuncType::new(vec![#params], vec![#returns]),
         ).expect("valid callable export").vmctx_and_body();
     });
     inits.extend(quote! {
@@ -117,8 +108,13 @@ fn convert_method(
     let method_sig = &method.sig;
     proxies.extend(quote! {
         #method_sig {
-            let f = self.#field_name.1;
-            unsafe { f(self.#field_name.0 #call_passthru_params) }
+            let (vmctx, f) = self.#field_name;
+            let call = move || unsafe { f(vmctx #call_passthru_params) };
+            match unsafe { self.instance.call_export(#wasm_name, vmctx, call) } {
+                Ok(result) => result,
+                // The trait cannot return the trap, it is the panic payload.
+                Err(trap) => ::std::panic::resume_unwind(Box::new(trap)),
+            }
         }
     });
 }
@@ -179,8 +175,8 @@ pub fn wasm_export(attr: TokenStream, item: TokenStream) -> TokenStream {
     };
     let extra = quote! {
         mod #extra_mod_indent {
-            use ::wasmtime_embed::{InstanceToken, WasmExport, InstanceCallableExport};
-            use ::wasmtime_embed::extra::{VMContext, VMFunctionBody, ir, isa};
+            use ::wasmtime_embed::{InstanceToken, WasmExport, FuncType, ValType};
+            use ::wasmtime_embed::extra::VMContext;
 
             pub struct Impl {
                 instance: InstanceToken,
@@ -197,28 +193,19 @@ pub fn wasm_export(attr: TokenStream, item: TokenStream) -> TokenStream {
     })
 }
 
-fn convert_method_sig2(
-    sig: &MethodSig,
-) -> (TokenStream2, TokenStream2, TokenStream2, TokenStream2) {
+fn wrap_method(method: &TraitItemMethod, definitions: &mut TokenStream2) {
+    let sig = &method.sig;
     if let FnArg::SelfRef(_) = sig.decl.inputs[0] {
         ()
     } else {
         panic!("&self is required for method");
     }
 
-    let name = sig.ident.clone();
-    let mut ty_args = TokenStream2::new();
-    let mut params = TokenStream2::new();
+    let mut closure_params = TokenStream2::new();
     let mut call_passthru_params = TokenStream2::new();
 
-    for param in &sig.decl.inputs {
+    for param in sig.decl.inputs.iter().skip(1) {
         match param {
-            FnArg::SelfRef(_) => {
-                ty_args.extend(quote! { vmctx: *mut VMContext });
-                params.extend(quote! {
-                    ir::AbiParam::special(ir::types::I64, ir::ArgumentPurpose::VMContext)
-                });
-            }
             FnArg::Captured(ArgCaptured {
                 pat:
                     Pat::Ident(PatIdent {
@@ -230,67 +217,32 @@ fn convert_method_sig2(
                 ty,
                 ..
             }) => {
-                ty_args.extend(quote! { , #ident: #ty });
-                let param_type = convert_type(ty);
-                params.extend(quote! { , ir::AbiParam::new(#param_type) });
-                if !call_passthru_params.is_empty() {
-                    call_passthru_params.extend(quote! { , });
-                }
-                call_passthru_params.extend(quote! { #ident });
+                // Validates the type.
+                convert_type(ty);
+                closure_params.extend(quote! { #ident: #ty, });
+                call_passthru_params.extend(quote! { #ident, });
             }
             _ => panic!("unsupported param type"),
         }
     }
 
-    let mut ty_ret = TokenStream2::new();
-    let mut returns = TokenStream2::new();
-    match sig.decl.output {
-        ReturnType::Default => (),
+    let ty_ret = match sig.decl.output {
+        ReturnType::Default => TokenStream2::new(),
         ReturnType::Type(_, ref ty) => {
-            ty_ret = quote! { -> #ty };
-            let return_type = convert_type(ty);
-            returns.extend(quote! {
-                ir::AbiParam::new(#return_type)
-            });
+            convert_type(ty);
+            quote! { -> #ty }
         }
-    }
-
-    let ty = quote! {
-        unsafe extern "sysv64" fn #name(#ty_args) #ty_ret
     };
 
-    (ty, params, returns, call_passthru_params)
-}
-
-fn wrap_method(
-    method: &TraitItemMethod,
-    extra_mod_indent: &Ident,
-    definitions: &mut TokenStream2,
-    wrapper_methods: &mut TokenStream2,
-) {
-    let (sig, params, returns, call_passthru_params) = convert_method_sig2(&method.sig);
-
-    let method_name = method.sig.ident.clone();
+    let method_name = sig.ident.clone();
     let wasm_name = method_name.to_string();
 
     definitions.extend(quote! {
-        let sig = module.signatures.push(
-            ir::Signature {
-                params: vec![#params],
-                returns: vec![#returns],
-                call_conv: isa::CallConv::SystemV,
-            }
-        );
-        let func = module.functions.push(sig);
-        module.exports.insert(
-            #wasm_name . to_owned(),
-            Export::Function(func),
-        );
-        finished_functions.push(#extra_mod_indent :: #method_name as *const VMFunctionBody);
-    });
-    wrapper_methods.extend(quote! {
-        pub (super) #sig {
-            get_state(vmctx).subject.borrow().#method_name(#call_passthru_params)
+        {
+            let state = state.clone();
+            builder.wrap(#wasm_name, move |#closure_params| #ty_ret {
+                state.subject.#method_name(#call_passthru_params)
+            });
         }
     });
 }
@@ -308,16 +260,10 @@ pub fn wasm_import(attr: TokenStream, item: TokenStream) -> TokenStream {
     let extra_mod_indent = Ident::new(&extra_mod_name, Span::call_site());
 
     let mut definitions = TokenStream2::new();
-    let mut wrapper_methods = TokenStream2::new();
     for item in &ast.items {
         match item {
             TraitItem::Method(ref method) => {
-                wrap_method(
-                    method,
-                    &extra_mod_indent,
-                    &mut definitions,
-                    &mut wrapper_methods,
-                );
+                wrap_method(method, &mut definitions);
             }
             _ => {
                 panic!("Unexpected trait type: {:?}", item);
@@ -329,44 +275,30 @@ pub fn wasm_import(attr: TokenStream, item: TokenStream) -> TokenStream {
         fn wrap_wasm_imports<T: #trait_ident + 'static>(
             subject: T
         ) -> ::wasmtime_embed::InstanceToken where Self: Sized {
-            use ::wasmtime_embed::extra::{
-                ir, isa, Export, Module, PrimaryMap, VMFunctionBody,
-            };
             use ::std::boxed::Box;
-            use ::std::cell::RefCell;
 
-            let mut finished_functions = PrimaryMap::new();
-            let mut module = Module::new();
-            
+            let mut builder = ::wasmtime_embed::HostModuleBuilder::new();
+            let state = builder.state(#extra_mod_indent :: State {
+                subject: Box::new(subject)
+            });
+
             #definitions
 
-            InstanceToken::from_raw_parts(
-                module,
-                finished_functions.into_boxed_slice(),
-                Box::new(#extra_mod_indent :: State {
-                    subject: RefCell::new(Box::new(subject))
-                })
-            )
+            builder.build()
         }
     });
     ast.items.extend(parse::<TraitItem>(wrap_method));
 
     let extra = quote! {
         #vis mod #extra_mod_indent {
-            use ::wasmtime_embed::{InstanceToken, WasmExport, InstanceCallableExport};
-            use ::wasmtime_embed::extra::{VMContext, VMFunctionBody, ir, isa};
             use ::std::boxed::Box;
-            use ::std::cell::RefCell;
 
+            // The methods take `&self`, so the subject is only ever shared
+            // and a method can call back into wasm, which calls the same or
+            // another method again.
             pub (super) struct State {
-                pub subject: RefCell<
-                    Box<dyn super::#trait_ident + 'static>
-                >,
-            }
-            unsafe fn get_state<'a>(vmctx: *mut VMContext) -> &'a mut State {
-                &mut *(&mut *vmctx).host_state().downcast_mut::<State>().unwrap()
+                pub subject: Box<dyn super::#trait_ident + 'static>,
             }
-            #wrapper_methods
         }
     };
 
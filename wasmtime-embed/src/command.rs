@@ -0,0 +1,170 @@
+use crate::context::ContextToken;
+use crate::func::Func;
+use crate::imports::ImportSet;
+use crate::instance::InstanceToken;
+use crate::instantiate::instantiate_in_context;
+use crate::module_info::ModuleParseError;
+use crate::trap::Trap;
+use crate::types::{FuncType, ValType};
+use crate::wasi::{WasiConfig, WasiOverrides};
+use crate::wat::to_binary;
+use failure::Error;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use wasmparser::{ModuleReader, SectionCode};
+use wasmtime_jit::RuntimeValue;
+
+const WASI_MODULE: &str = "wasi_unstable";
+
+#[derive(Fail, Debug)]
+#[fail(display = "Module exports both _start and _initialize")]
+pub struct AmbiguousWasiModule;
+
+#[derive(Fail, Debug)]
+#[fail(display = "Module is a WASI command exporting _start, not a reactor")]
+pub struct NotAReactor;
+
+#[derive(Fail, Debug)]
+#[fail(display = "Reactor called proc_exit({}) in _initialize", _0)]
+pub struct ExitedDuringInitialize(pub i32);
+
+/// How a WASI command finished.
+#[derive(Debug)]
+pub enum ExitStatus {
+    /// `_start` returned.
+    Returned,
+    /// The command called `proc_exit` with the code.
+    Exited(i32),
+    /// The command trapped.
+    Trapped(Trap),
+}
+
+impl ExitStatus {
+    /// Returns the exit code, which is 0 when `_start` returned.
+    pub fn code(&self) -> Option<i32> {
+        match self {
+            ExitStatus::Returned => Some(0),
+            ExitStatus::Exited(code) => Some(*code),
+            ExitStatus::Trapped(_) => None,
+        }
+    }
+
+    pub fn success(&self) -> bool {
+        self.code() == Some(0)
+    }
+}
+
+/// Adds the "wasi_unstable" module built from `wasi` to `imports`, returns
+/// the cell receiving the `proc_exit` code. `proc_exit`, which would exit
+/// the host process, records the code and traps instead.
+fn add_wasi_imports(
+    wasi: &mut WasiConfig,
+    imports: &mut HashMap<String, ImportSet>,
+    context: &ContextToken,
+) -> Result<Rc<Cell<Option<i32>>>, Error> {
+    let exit_code = Rc::new(Cell::new(None));
+    let recorded = exit_code.clone();
+    let proc_exit = Func::new(FuncType::new(vec![ValType::I32], vec![]), move |args| {
+        if let RuntimeValue::I32(code) = args[0] {
+            recorded.set(Some(code));
+        }
+        Err(Trap::new("proc_exit"))
+    });
+    let mut overrides = WasiOverrides::new();
+    overrides.insert("proc_exit".to_owned(), proc_exit);
+    let wasi = wasi.build_with(context, overrides)?;
+    imports.insert(WASI_MODULE.to_owned(), ImportSet::InstanceExports(wasi));
+    Ok(exit_code)
+}
+
+/// Instantiates WASI command `data` with `imports` and the "wasi_unstable"
+/// module built from `wasi`, and runs its `_start` function.
+pub fn run_command_in_context(
+    data: &[u8],
+    wasi: &mut WasiConfig,
+    mut imports: HashMap<String, ImportSet>,
+    context: ContextToken,
+) -> Result<ExitStatus, Error> {
+    let exit_code = add_wasi_imports(wasi, &mut imports, &context)?;
+
+    let result = instantiate_in_context(data, imports, context)
+        .and_then(|instance| instance.get_typed_func::<(), ()>("_start")?.call(()));
+    if let Some(code) = exit_code.get() {
+        return Ok(ExitStatus::Exited(code));
+    }
+    match result {
+        Ok(()) => Ok(ExitStatus::Returned),
+        Err(e) => match e.downcast::<Trap>() {
+            Ok(trap) => Ok(ExitStatus::Trapped(trap)),
+            Err(e) => Err(e),
+        },
+    }
+}
+
+/// Runs WASI command `data`, e.g.
+///
+/// ```ignore
+/// let status = run_command(&hello_wasm, WasiConfig::new().arg("hello"))?;
+/// ```
+pub fn run_command(data: &[u8], wasi: &mut WasiConfig) -> Result<ExitStatus, Error> {
+    run_command_in_context(data, wasi, HashMap::new(), ContextToken::create())
+}
+
+/// Returns whether module `data` exports `_start` and `_initialize`.
+fn entry_points(data: &[u8]) -> Result<(bool, bool), ModuleParseError> {
+    let (mut has_start, mut has_initialize) = (false, false);
+    let mut reader = ModuleReader::new(data)?;
+    while !reader.eof() {
+        let section = reader.read()?;
+        if let SectionCode::Export = section.code {
+            for export in section.get_export_section_reader()? {
+                match export?.field {
+                    "_start" => has_start = true,
+                    "_initialize" => has_initialize = true,
+                    _ => (),
+                }
+            }
+        }
+    }
+    Ok((has_start, has_initialize))
+}
+
+/// Instantiates WASI reactor (library) `data` with `imports` and the
+/// "wasi_unstable" module built from `wasi`. The `_initialize` function, if
+/// exported, is run before the instance is returned; the instance is then
+/// used as any other, e.g. via `get_typed_func` or `wasm_export_impl!`.
+/// Commands, i.e. modules exporting `_start`, are rejected with `NotAReactor`.
+pub fn instantiate_reactor_in_context(
+    data: &[u8],
+    wasi: &mut WasiConfig,
+    mut imports: HashMap<String, ImportSet>,
+    context: ContextToken,
+) -> Result<InstanceToken, Error> {
+    let binary = to_binary(data)?;
+    let (has_start, has_initialize) = entry_points(&binary)?;
+    if has_start && has_initialize {
+        return Err(AmbiguousWasiModule.into());
+    }
+    // The exports of a command may only be called from `_start`.
+    if has_start {
+        return Err(NotAReactor.into());
+    }
+
+    let exit_code = add_wasi_imports(wasi, &mut imports, &context)?;
+    let instance = instantiate_in_context(&binary, imports, context)?;
+    if has_initialize {
+        let result = instance
+            .get_typed_func::<(), ()>("_initialize")?
+            .call(());
+        if let Some(code) = exit_code.get() {
+            return Err(ExitedDuringInitialize(code).into());
+        }
+        result?;
+    }
+    Ok(instance)
+}
+
+pub fn instantiate_reactor(data: &[u8], wasi: &mut WasiConfig) -> Result<InstanceToken, Error> {
+    instantiate_reactor_in_context(data, wasi, HashMap::new(), ContextToken::create())
+}
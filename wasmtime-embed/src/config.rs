@@ -0,0 +1,140 @@
+/// Settings applied to contexts created with `ContextToken::with_config`
+/// and to the instances created in them.
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    consume_fuel: bool,
+    initial_fuel: u64,
+    interruptable: bool,
+    debug_info: bool,
+    perf_map: bool,
+    threads: bool,
+    reference_types: bool,
+    simd: bool,
+    bulk_memory: bool,
+    max_call_depth: Option<usize>,
+}
+
+/// Default of `Config::max_call_depth`.
+const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+impl Config {
+    pub fn new() -> Config {
+        Config::default()
+    }
+
+    /// Instruments modules to consume fuel while running. An instance traps
+    /// with `TrapCode::OutOfFuel` once its fuel is exhausted.
+    ///
+    /// Every metered block, e.g. every loop iteration, calls into the host
+    /// to charge the fuel, which costs a trampoline call with argument
+    /// marshalling: tight loops run several times slower.
+    pub fn consume_fuel(&mut self, enable: bool) -> &mut Config {
+        self.consume_fuel = enable;
+        self
+    }
+
+    /// Fuel given to every new instance, see `InstanceToken::add_fuel`.
+    pub fn initial_fuel(&mut self, fuel: u64) -> &mut Config {
+        self.initial_fuel = fuel;
+        self
+    }
+
+    /// Instruments modules to check for interrupts, see
+    /// `InstanceToken::interrupt_handle`.
+    pub fn interruptable(&mut self, enable: bool) -> &mut Config {
+        self.interruptable = enable;
+        self
+    }
+
+    /// Generates native debug info for compiled modules from their DWARF
+    /// and registers it through the GDB JIT interface, so gdb and lldb can
+    /// break in the guest source. Line info of the modules instrumented for
+    /// fuel, interrupts or limits is not accurate.
+    pub fn debug_info(&mut self, enable: bool) -> &mut Config {
+        self.debug_info = enable;
+        self
+    }
+
+    /// Writes compiled functions, named after the module "name" section,
+    /// to `/tmp/perf-<pid>.map` to make them visible in `perf` profiles.
+    pub fn perf_map(&mut self, enable: bool) -> &mut Config {
+        self.perf_map = enable;
+        self
+    }
+
+    /// Enables the threads proposal in validation.
+    /// Modules using it fail to instantiate with `UnsupportedFeature`.
+    pub fn wasm_threads(&mut self, enable: bool) -> &mut Config {
+        self.threads = enable;
+        self
+    }
+
+    /// Enables the reference types proposal in validation.
+    /// Modules using it fail to instantiate with `UnsupportedFeature`.
+    pub fn wasm_reference_types(&mut self, enable: bool) -> &mut Config {
+        self.reference_types = enable;
+        self
+    }
+
+    /// Enables the SIMD proposal in validation.
+    /// Modules using it fail to instantiate with `UnsupportedFeature`.
+    pub fn wasm_simd(&mut self, enable: bool) -> &mut Config {
+        self.simd = enable;
+        self
+    }
+
+    /// Enables the bulk memory proposal in validation.
+    /// Modules using it fail to instantiate with `UnsupportedFeature`.
+    pub fn wasm_bulk_memory(&mut self, enable: bool) -> &mut Config {
+        self.bulk_memory = enable;
+        self
+    }
+
+    /// Limits the nesting of calls from the host into the instances, e.g.
+    /// of host functions calling back into wasm. A call beyond `depth`
+    /// traps with `TrapCode::StackOverflow`. Defaults to 1000.
+    pub fn max_call_depth(&mut self, depth: usize) -> &mut Config {
+        self.max_call_depth = Some(depth);
+        self
+    }
+
+    pub(crate) fn fuel_enabled(&self) -> bool {
+        self.consume_fuel
+    }
+
+    pub(crate) fn fuel(&self) -> u64 {
+        self.initial_fuel
+    }
+
+    pub(crate) fn interrupts_enabled(&self) -> bool {
+        self.interruptable
+    }
+
+    pub(crate) fn debug_info_enabled(&self) -> bool {
+        self.debug_info
+    }
+
+    pub(crate) fn perf_map_enabled(&self) -> bool {
+        self.perf_map
+    }
+
+    pub(crate) fn threads_enabled(&self) -> bool {
+        self.threads
+    }
+
+    pub(crate) fn reference_types_enabled(&self) -> bool {
+        self.reference_types
+    }
+
+    pub(crate) fn simd_enabled(&self) -> bool {
+        self.simd
+    }
+
+    pub(crate) fn bulk_memory_enabled(&self) -> bool {
+        self.bulk_memory
+    }
+
+    pub(crate) fn call_depth_limit(&self) -> usize {
+        self.max_call_depth.unwrap_or(DEFAULT_MAX_CALL_DEPTH)
+    }
+}
@@ -1,23 +1,100 @@
-use std::cell::{RefCell, RefMut};
+use std::any::Any;
+use std::cell::{Cell, RefCell, RefMut};
 use std::hash::{Hash, Hasher};
 use std::rc::Rc;
 
+use crate::config::Config;
+use crate::limits::ResourceLimiter;
+use crate::store::{LiveGuard, LiveKind};
+use cranelift_codegen::isa::TargetIsa;
 use wasmtime_jit::Context;
 
 #[derive(Clone)]
-pub struct ContextToken(Rc<RefCell<Context>>);
+pub struct ContextToken(Rc<ContextData>);
+
+struct ContextData {
+    context: RefCell<Context>,
+    config: Config,
+    limiter: RefCell<Option<Rc<dyn ResourceLimiter>>>,
+    instance_count: Cell<usize>,
+    // Code compiled into the context lives as long as the context.
+    code: RefCell<Vec<LiveGuard>>,
+    // Dropped after `context`, i.e. after the instances named in it.
+    attached: RefCell<Vec<Box<dyn Any>>>,
+    _live: LiveGuard,
+}
 
 impl ContextToken {
     pub fn new(context: Context) -> ContextToken {
-        ContextToken(Rc::new(RefCell::new(context)))
+        ContextToken::from_parts(context, Config::default())
     }
 
     pub fn create() -> ContextToken {
-        ContextToken(Rc::new(RefCell::new(create_context())))
+        ContextToken::with_config(Config::default())
+    }
+
+    pub fn with_config(config: Config) -> ContextToken {
+        let context = create_context(config.debug_info_enabled());
+        ContextToken::from_parts(context, config)
+    }
+
+    fn from_parts(context: Context, config: Config) -> ContextToken {
+        ContextToken(Rc::new(ContextData {
+            context: RefCell::new(context),
+            config,
+            limiter: RefCell::new(None),
+            instance_count: Cell::new(0),
+            code: RefCell::new(Vec::new()),
+            attached: RefCell::new(Vec::new()),
+            _live: LiveGuard::new(LiveKind::Context, 1),
+        }))
+    }
+
+    /// Creates an empty context with the same config and limiter.
+    pub(crate) fn renew(&self) -> ContextToken {
+        let context = ContextToken::with_config(self.0.config.clone());
+        *context.0.limiter.borrow_mut() = self.limiter();
+        context
     }
 
     pub fn context(&mut self) -> RefMut<Context> {
-        self.0.borrow_mut()
+        self.0.context.borrow_mut()
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.0.config
+    }
+
+    /// Sets limiter consulted by the instances subsequently created in
+    /// the context.
+    pub fn set_limiter<L: ResourceLimiter + 'static>(&self, limiter: L) {
+        *self.0.limiter.borrow_mut() = Some(Rc::new(limiter));
+    }
+
+    pub(crate) fn limiter(&self) -> Option<Rc<dyn ResourceLimiter>> {
+        self.0.limiter.borrow().clone()
+    }
+
+    /// Returns the number of instances created in the context so far.
+    pub(crate) fn instance_count(&self) -> usize {
+        self.0.instance_count.get()
+    }
+
+    /// Counts an instance successfully created in the context.
+    pub(crate) fn count_instance(&self) {
+        self.0.instance_count.set(self.0.instance_count.get() + 1);
+    }
+
+    /// Accounts `bytes` of code compiled into the context in `live_counts`.
+    pub(crate) fn add_code_bytes(&self, bytes: usize) {
+        let guard = LiveGuard::new(LiveKind::CodeBytes, bytes);
+        self.0.code.borrow_mut().push(guard);
+    }
+
+    /// Keeps `value` until the context and the instances named in it are
+    /// dropped.
+    pub(crate) fn attach<T: 'static>(&self, value: T) {
+        self.0.attached.borrow_mut().push(Box::new(value));
     }
 }
 
@@ -26,12 +103,7 @@ impl Hash for ContextToken {
     where
         H: Hasher,
     {
-        unsafe {
-            let ptr = Rc::into_raw(self.0.clone());
-            let _ = Rc::from_raw(ptr);
-            ptr
-        }
-        .hash(state)
+        (&*self.0 as *const ContextData).hash(state)
     }
 }
 
@@ -43,14 +115,15 @@ impl PartialEq for ContextToken {
     }
 }
 
-pub(crate) fn create_context() -> Context {
-    let generate_debug_info = false;
-    let isa = {
-        let isa_builder =
-            cranelift_native::builder().expect("host machine is not a supported target");
-        let flag_builder = cranelift_codegen::settings::builder();
-        isa_builder.finish(cranelift_codegen::settings::Flags::new(flag_builder))
-    };
+pub(crate) fn create_isa() -> Box<dyn TargetIsa> {
+    let isa_builder =
+        cranelift_native::builder().expect("host machine is not a supported target");
+    let flag_builder = cranelift_codegen::settings::builder();
+    isa_builder.finish(cranelift_codegen::settings::Flags::new(flag_builder))
+}
+
+pub(crate) fn create_context(generate_debug_info: bool) -> Context {
+    let isa = create_isa();
 
     let mut context = Context::with_isa(isa);
     context.set_debug_info(generate_debug_info);
@@ -0,0 +1,98 @@
+use crate::func::Func;
+use crate::host_module::HostModuleBuilder;
+use crate::instance::InstanceToken;
+use crate::instrument::InstrumentationFailed;
+use crate::interrupt::InterruptHandle;
+use crate::trap::{Trap, TrapCode};
+use crate::types::{FuncType, ValType};
+use failure::Error;
+use parity_wasm::elements;
+use pwasm_utils::rules;
+use std::cell::Cell;
+use std::rc::Rc;
+use wasmtime_jit::RuntimeValue;
+
+/// Name of the import module that provides the fuel counter and interrupt
+/// checks to instrumented modules.
+pub(crate) const FUEL_MODULE: &str = "__wasmtime_embed_fuel";
+
+/// Name of the function, which `pwasm_utils` calls with the cost of every
+/// metered block.
+const FUEL_FUNC: &str = "gas";
+
+#[derive(Fail, Debug)]
+#[fail(display = "Fuel consumption is not enabled for the instance")]
+pub struct FuelNotEnabled;
+
+/// Fuel left to an instance.
+#[derive(Clone)]
+pub(crate) struct Fuel(Rc<Cell<u64>>);
+
+impl Fuel {
+    pub fn new(fuel: u64) -> Fuel {
+        Fuel(Rc::new(Cell::new(fuel)))
+    }
+
+    pub fn add(&self, fuel: u64) {
+        self.0.set(self.0.get().saturating_add(fuel));
+    }
+
+    pub fn remaining(&self) -> u64 {
+        self.0.get()
+    }
+
+    fn consume(&self, fuel: u64) -> Result<(), Trap> {
+        let remaining = self.0.get();
+        if fuel > remaining {
+            self.0.set(0);
+            return Err(Trap::with_code(TrapCode::OutOfFuel, "all fuel consumed"));
+        }
+        self.0.set(remaining - fuel);
+        Ok(())
+    }
+}
+
+/// Injects a call to `FUEL_MODULE.gas` at the start of every metered block.
+pub(crate) fn inject_fuel(module: elements::Module) -> Result<elements::Module, Error> {
+    let mut module = pwasm_utils::inject_gas_counter(module, &rules::Set::default())
+        .map_err(|_| InstrumentationFailed("gas counter injection".to_owned()))?;
+
+    // `pwasm_utils` appends the counter import to the "env" module, which is
+    // likely used by the guest too: move it into a module of its own.
+    let gas_import = module
+        .import_section_mut()
+        .and_then(|section| {
+            section
+                .entries_mut()
+                .iter_mut()
+                .rev()
+                .find(|entry| entry.module() == "env" && entry.field() == FUEL_FUNC)
+        })
+        .ok_or_else(|| InstrumentationFailed("gas import not found".to_owned()))?;
+    *gas_import.module_mut() = FUEL_MODULE.to_owned();
+
+    Ok(module)
+}
+
+/// Creates the `FUEL_MODULE` instance that charges `fuel` and checks
+/// `interrupt` on every metered block.
+pub(crate) fn create_fuel_import(
+    fuel: Option<&Fuel>,
+    interrupt: Option<&InterruptHandle>,
+) -> InstanceToken {
+    let fuel = fuel.cloned();
+    let interrupt = interrupt.cloned();
+    let gas = Func::new(FuncType::new(vec![ValType::I32], vec![]), move |args| {
+        if let Some(ref interrupt) = interrupt {
+            interrupt.check()?;
+        }
+        if let Some(ref fuel) = fuel {
+            match args[0] {
+                RuntimeValue::I32(cost) => fuel.consume(cost as u32 as u64)?,
+                _ => unreachable!(),
+            }
+        }
+        Ok(vec![])
+    });
+    HostModuleBuilder::new().func(FUEL_FUNC, gas).build()
+}
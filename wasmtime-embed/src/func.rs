@@ -0,0 +1,437 @@
+use crate::context::create_isa;
+use crate::instance::InstanceToken;
+use crate::store::{with_store_data, LiveGuard, LiveKind};
+use crate::trampoline::{make_trampoline, VALUE_SIZE};
+use crate::trap::{record_trap, Trap};
+use crate::types::{FuncType, ValType};
+use cranelift_codegen::ir;
+use cranelift_entity::PrimaryMap;
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use wasmtime_environ::{Export, Module};
+use wasmtime_jit::{CodeMemory, RuntimeValue};
+use wasmtime_runtime::{VMContext, VMFunctionBody};
+
+/// Name under which the single function of a `Func` instance is exported.
+const FUNC_EXPORT_NAME: &str = "";
+
+/// Rust types that can be passed to and from wasm functions.
+pub trait WasmTy: Copy + 'static {
+    fn val_type() -> ValType;
+
+    #[doc(hidden)]
+    fn from_value(value: RuntimeValue) -> Self;
+
+    #[doc(hidden)]
+    fn into_value(self) -> RuntimeValue;
+}
+
+impl WasmTy for i32 {
+    fn val_type() -> ValType {
+        ValType::I32
+    }
+
+    fn from_value(value: RuntimeValue) -> Self {
+        match value {
+            RuntimeValue::I32(v) => v,
+            _ => panic!("expected i32 value"),
+        }
+    }
+
+    fn into_value(self) -> RuntimeValue {
+        RuntimeValue::I32(self)
+    }
+}
+
+impl WasmTy for u32 {
+    fn val_type() -> ValType {
+        ValType::I32
+    }
+
+    fn from_value(value: RuntimeValue) -> Self {
+        match value {
+            RuntimeValue::I32(v) => v as u32,
+            _ => panic!("expected u32 value"),
+        }
+    }
+
+    fn into_value(self) -> RuntimeValue {
+        RuntimeValue::I32(self as i32)
+    }
+}
+
+impl WasmTy for i64 {
+    fn val_type() -> ValType {
+        ValType::I64
+    }
+
+    fn from_value(value: RuntimeValue) -> Self {
+        match value {
+            RuntimeValue::I64(v) => v,
+            _ => panic!("expected i64 value"),
+        }
+    }
+
+    fn into_value(self) -> RuntimeValue {
+        RuntimeValue::I64(self)
+    }
+}
+
+impl WasmTy for u64 {
+    fn val_type() -> ValType {
+        ValType::I64
+    }
+
+    fn from_value(value: RuntimeValue) -> Self {
+        match value {
+            RuntimeValue::I64(v) => v as u64,
+            _ => panic!("expected u64 value"),
+        }
+    }
+
+    fn into_value(self) -> RuntimeValue {
+        RuntimeValue::I64(self as i64)
+    }
+}
+
+impl WasmTy for f32 {
+    fn val_type() -> ValType {
+        ValType::F32
+    }
+
+    fn from_value(value: RuntimeValue) -> Self {
+        match value {
+            RuntimeValue::F32(v) => f32::from_bits(v),
+            _ => panic!("expected f32 value"),
+        }
+    }
+
+    fn into_value(self) -> RuntimeValue {
+        RuntimeValue::F32(self.to_bits())
+    }
+}
+
+impl WasmTy for f64 {
+    fn val_type() -> ValType {
+        ValType::F64
+    }
+
+    fn from_value(value: RuntimeValue) -> Self {
+        match value {
+            RuntimeValue::F64(v) => f64::from_bits(v),
+            _ => panic!("expected f64 value"),
+        }
+    }
+
+    fn into_value(self) -> RuntimeValue {
+        RuntimeValue::F64(self.to_bits())
+    }
+}
+
+/// Rust types that can be returned from host functions: `()` or a single `WasmTy`.
+pub trait WasmRet: 'static {
+    fn val_types() -> Vec<ValType>;
+
+    #[doc(hidden)]
+    fn into_values(self) -> Vec<RuntimeValue>;
+}
+
+impl WasmRet for () {
+    fn val_types() -> Vec<ValType> {
+        Vec::new()
+    }
+
+    fn into_values(self) -> Vec<RuntimeValue> {
+        Vec::new()
+    }
+}
+
+impl<T: WasmTy> WasmRet for T {
+    fn val_types() -> Vec<ValType> {
+        vec![T::val_type()]
+    }
+
+    fn into_values(self) -> Vec<RuntimeValue> {
+        vec![self.into_value()]
+    }
+}
+
+/// Conversion of Rust closures into host functions, see `Func::wrap`.
+pub trait IntoFunc<Params, Results> {
+    fn into_func(self) -> Func;
+}
+
+/// Conversion of Rust closures taking the store data `T` into host
+/// functions, see `Func::wrap_with_data`.
+pub trait IntoFuncWithData<T, Params, Results> {
+    fn into_func_with_data(self) -> Func;
+}
+
+/// Host function that can be exported to wasm, e.g. via `HostModuleBuilder`.
+///
+/// Every `Func` created from a closure lives in its own synthetic instance,
+/// which owns the closure as the instance host state.
+#[derive(Clone)]
+pub struct Func {
+    instance: InstanceToken,
+    export_name: String,
+    signature: ir::Signature,
+}
+
+impl Func {
+    /// Creates host function from a closure, e.g.
+    /// `Func::wrap(|a: i32, b: i32| -> i32 { a + b })`. A panic of the
+    /// closure traps the calling wasm code.
+    pub fn wrap<Params, Results>(f: impl IntoFunc<Params, Results>) -> Func {
+        f.into_func()
+    }
+
+    /// Creates host function from a closure receiving the data of the store
+    /// the calling instance belongs to, e.g.
+    /// `Func::wrap_with_data(|total: &mut i64, a: i32| *total += i64::from(a))`.
+    /// The call traps if the store data is missing, has another type or is
+    /// already borrowed, see `with_store_data`.
+    pub fn wrap_with_data<T, Params, Results>(
+        f: impl IntoFuncWithData<T, Params, Results>,
+    ) -> Func {
+        f.into_func_with_data()
+    }
+
+    /// Creates host function of type `ty` that receives and returns
+    /// dynamically typed values. Returning `Err` or panicking traps the
+    /// calling wasm code.
+    pub fn new<F>(ty: FuncType, callback: F) -> Func
+    where
+        F: Fn(&[RuntimeValue]) -> Result<Vec<RuntimeValue>, Trap> + 'static,
+    {
+        let isa = create_isa();
+        let mut code_memory = CodeMemory::new();
+        let signature = ty.signature();
+        let (body, code_size) =
+            make_trampoline(&*isa, &mut code_memory, &signature, dynamic_stub);
+        code_memory.publish();
+
+        Func::from_raw_parts(
+            signature,
+            body,
+            Box::new(DynamicFuncState {
+                ty,
+                callback: Box::new(callback),
+                _code_memory: code_memory,
+                _code_live: LiveGuard::new(LiveKind::CodeBytes, code_size),
+            }),
+        )
+    }
+
+    pub(crate) fn from_raw_parts(
+        signature: ir::Signature,
+        body: *const VMFunctionBody,
+        state: Box<dyn Any>,
+    ) -> Func {
+        let mut module = Module::new();
+        let sig = module.signatures.push(signature.clone());
+        let func = module.functions.push(sig);
+        module
+            .exports
+            .insert(FUNC_EXPORT_NAME.to_owned(), Export::Function(func));
+
+        let mut finished_functions = PrimaryMap::new();
+        finished_functions.push(body);
+
+        Func {
+            instance: InstanceToken::from_raw_parts(
+                module,
+                finished_functions.into_boxed_slice(),
+                state,
+            ),
+            export_name: FUNC_EXPORT_NAME.to_owned(),
+            signature,
+        }
+    }
+
+    /// Refers to the function exported by `instance` as `name`, e.g. to
+    /// re-export it with `HostModuleBuilder::func`.
+    pub fn from_export(instance: &InstanceToken, name: &str) -> Option<Func> {
+        let mut handle = instance.handle().clone();
+        match handle.lookup(name) {
+            Some(wasmtime_runtime::Export::Function { signature, .. }) => Some(Func {
+                instance: instance.clone(),
+                export_name: name.to_owned(),
+                signature,
+            }),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn signature(&self) -> &ir::Signature {
+        &self.signature
+    }
+
+    pub fn ty(&self) -> FuncType {
+        FuncType::from_signature(&self.signature).expect("wasm signature")
+    }
+
+    pub(crate) fn instance(&self) -> &InstanceToken {
+        &self.instance
+    }
+
+    pub(crate) fn vmctx_and_body(&self) -> (*mut VMContext, *const VMFunctionBody) {
+        self.instance
+            .get_callable_export(&self.export_name, &self.ty())
+            .expect("func export")
+            .vmctx_and_body()
+    }
+}
+
+struct DynamicFuncState {
+    ty: FuncType,
+    callback: Box<dyn Fn(&[RuntimeValue]) -> Result<Vec<RuntimeValue>, Trap>>,
+    // Keeps the trampoline code alive.
+    _code_memory: CodeMemory,
+    _code_live: LiveGuard,
+}
+
+unsafe fn read_value(ptr: *const u8, ty: ValType) -> RuntimeValue {
+    match ty {
+        ValType::I32 => RuntimeValue::I32(*(ptr as *const i32)),
+        ValType::I64 => RuntimeValue::I64(*(ptr as *const i64)),
+        ValType::F32 => RuntimeValue::F32(*(ptr as *const u32)),
+        ValType::F64 => RuntimeValue::F64(*(ptr as *const u64)),
+    }
+}
+
+unsafe fn write_value(ptr: *mut u8, value: &RuntimeValue) {
+    match *value {
+        RuntimeValue::I32(i) => *(ptr as *mut i32) = i,
+        RuntimeValue::I64(i) => *(ptr as *mut i64) = i,
+        RuntimeValue::F32(f) => *(ptr as *mut u32) = f,
+        RuntimeValue::F64(f) => *(ptr as *mut u64) = f,
+    }
+}
+
+unsafe extern "sysv64" fn dynamic_stub(vmctx: *mut VMContext, values_vec: *mut u8) -> u32 {
+    // Unwinding through the wasm frames is undefined behavior, so a panic
+    // of the callback traps like a returned error.
+    let result = panic::catch_unwind(AssertUnwindSafe(|| call_dynamic(vmctx, values_vec)))
+        .unwrap_or_else(|payload| Err(panic_trap(payload)));
+    match result {
+        Ok(()) => 0,
+        Err(trap) => {
+            record_trap(trap);
+            1
+        }
+    }
+}
+
+unsafe fn call_dynamic(vmctx: *mut VMContext, values_vec: *mut u8) -> Result<(), Trap> {
+    let state = (&mut *vmctx)
+        .host_state()
+        .downcast_ref::<DynamicFuncState>()
+        .expect("dynamic func state");
+
+    let args = state
+        .ty
+        .params()
+        .iter()
+        .enumerate()
+        .map(|(i, ty)| read_value(values_vec.add(i * VALUE_SIZE), *ty))
+        .collect::<Vec<_>>();
+
+    let results = (state.callback)(&args)?;
+
+    let types_match = results.len() == state.ty.results().len()
+        && results
+            .iter()
+            .zip(state.ty.results())
+            .all(|(value, ty)| ValType::from_ir_type(value.value_type()) == Some(*ty));
+    if !types_match {
+        return Err(Trap::new(format!(
+            "host function returned values of wrong type, expected {}",
+            state.ty
+        )));
+    }
+
+    for (i, value) in results.iter().enumerate() {
+        write_value(values_vec.add(i * VALUE_SIZE), value);
+    }
+    Ok(())
+}
+
+fn panic_trap(payload: Box<dyn Any + Send>) -> Trap {
+    // A `#[wasm_export]` proxy called by the host function trapped.
+    let payload = match payload.downcast::<Trap>() {
+        Ok(trap) => return *trap,
+        Err(payload) => payload,
+    };
+    let message = match payload.downcast_ref::<&str>() {
+        Some(message) => (*message).to_owned(),
+        None => match payload.downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => "unknown panic".to_owned(),
+        },
+    };
+    Trap::new(format!("host function panicked: {}", message))
+}
+
+// The closures are called through the `Func::new` trampoline rather than
+// directly from wasm, so their panics trap instead of unwinding through the
+// wasm frames.
+macro_rules! wrap_closure {
+    ($($arg_t:ident $arg:ident),*) => {
+        impl<F, $($arg_t,)* R> IntoFunc<($($arg_t,)*), R> for F
+        where
+            F: Fn($($arg_t),*) -> R + 'static,
+            $($arg_t: WasmTy,)*
+            R: WasmRet,
+        {
+            fn into_func(self) -> Func {
+                let ty = FuncType::new(vec![$($arg_t::val_type()),*], R::val_types());
+                Func::new(ty, move |args| {
+                    #[allow(unused_mut, unused_variables)]
+                    let mut args = args.iter().cloned();
+                    $(let $arg = $arg_t::from_value(args.next().expect("argument"));)*
+                    Ok(self($($arg),*).into_values())
+                })
+            }
+        }
+    };
+}
+
+macro_rules! wrap_closure_with_data {
+    ($($arg_t:ident $arg:ident),*) => {
+        impl<T, F, $($arg_t,)* R> IntoFuncWithData<T, ($($arg_t,)*), R> for F
+        where
+            T: 'static,
+            F: Fn(&mut T $(, $arg_t)*) -> R + 'static,
+            $($arg_t: WasmTy,)*
+            R: WasmRet,
+        {
+            fn into_func_with_data(self) -> Func {
+                let ty = FuncType::new(vec![$($arg_t::val_type()),*], R::val_types());
+                Func::new(ty, move |args| {
+                    #[allow(unused_mut, unused_variables)]
+                    let mut args = args.iter().cloned();
+                    $(let $arg = $arg_t::from_value(args.next().expect("argument"));)*
+                    let results = with_store_data(|data: &mut T| self(data $(, $arg)*))?;
+                    Ok(results.into_values())
+                })
+            }
+        }
+    };
+}
+
+wrap_closure!();
+wrap_closure!(A1 a1);
+wrap_closure!(A1 a1, A2 a2);
+wrap_closure!(A1 a1, A2 a2, A3 a3);
+wrap_closure!(A1 a1, A2 a2, A3 a3, A4 a4);
+wrap_closure!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5);
+wrap_closure!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6);
+
+wrap_closure_with_data!();
+wrap_closure_with_data!(A1 a1);
+wrap_closure_with_data!(A1 a1, A2 a2);
+wrap_closure_with_data!(A1 a1, A2 a2, A3 a3);
+wrap_closure_with_data!(A1 a1, A2 a2, A3 a3, A4 a4);
+wrap_closure_with_data!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5);
+wrap_closure_with_data!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6);
@@ -0,0 +1,251 @@
+use crate::func::{Func, IntoFunc, IntoFuncWithData};
+use crate::instance::InstanceToken;
+use crate::types::{MemoryType, TableType};
+use cranelift_entity::PrimaryMap;
+use cranelift_wasm::{Global, GlobalInit};
+use std::any::Any;
+use std::collections::HashSet;
+use std::rc::Rc;
+use wasmtime_environ::{Export, MemoryPlan, Module, TablePlan, Tunables};
+use wasmtime_jit::RuntimeValue;
+use wasmtime_runtime::{
+    self, Imports, VMFunctionImport, VMGlobalImport, VMMemoryImport, VMTableImport,
+};
+
+/// Assembles host functions, globals, memories and tables into a single
+/// import module, e.g.
+///
+/// ```ignore
+/// let test = HostModuleBuilder::new()
+///     .wrap("callback", |c: u32| println!("callback: {}", c))
+///     .memory("memory", MemoryType::new(1, None))
+///     .build();
+/// imports.insert(String::from("test"), ImportSet::InstanceExports(test));
+/// ```
+pub struct HostModuleBuilder {
+    funcs: Vec<(String, Func)>,
+    globals: Vec<(String, RuntimeValue, bool)>,
+    memories: Vec<(String, MemoryType)>,
+    tables: Vec<(String, TableType)>,
+    // Memories, tables and globals of other instances, imported and exported
+    // again under their names.
+    reexports: Vec<(String, wasmtime_runtime::Export)>,
+    dependencies: Vec<InstanceToken>,
+    state: Option<Rc<dyn Any>>,
+}
+
+impl HostModuleBuilder {
+    pub fn new() -> HostModuleBuilder {
+        HostModuleBuilder {
+            funcs: Vec::new(),
+            globals: Vec::new(),
+            memories: Vec::new(),
+            tables: Vec::new(),
+            reexports: Vec::new(),
+            dependencies: Vec::new(),
+            state: None,
+        }
+    }
+
+    pub fn func(&mut self, name: &str, func: Func) -> &mut HostModuleBuilder {
+        self.funcs.push((name.to_owned(), func));
+        self
+    }
+
+    pub fn wrap<Params, Results>(
+        &mut self,
+        name: &str,
+        f: impl IntoFunc<Params, Results>,
+    ) -> &mut HostModuleBuilder {
+        self.func(name, Func::wrap(f))
+    }
+
+    /// Adds function receiving the store data, see `Func::wrap_with_data`.
+    pub fn wrap_with_data<T, Params, Results>(
+        &mut self,
+        name: &str,
+        f: impl IntoFuncWithData<T, Params, Results>,
+    ) -> &mut HostModuleBuilder {
+        self.func(name, Func::wrap_with_data(f))
+    }
+
+    /// Adds the exports of `instance`, which are not yet defined in the
+    /// builder, e.g. to override some functions of an import module. The
+    /// memories, tables and globals stay the ones of `instance`.
+    pub fn reexport(&mut self, instance: &InstanceToken) -> &mut HostModuleBuilder {
+        let mut exports: Vec<(String, Export)> = instance
+            .handle()
+            .module_ref()
+            .exports
+            .iter()
+            .map(|(name, export)| (name.clone(), export.clone()))
+            .collect();
+        exports.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (name, export) in exports {
+            if self.defines(&name) {
+                continue;
+            }
+            if let Export::Function(_) = export {
+                let func = Func::from_export(instance, &name).expect("function export");
+                self.funcs.push((name, func));
+            } else {
+                let export = instance.handle().clone().lookup(&name).expect("export");
+                self.reexports.push((name, export));
+            }
+        }
+        self.dependencies.push(instance.clone());
+        self
+    }
+
+    fn defines(&self, name: &str) -> bool {
+        self.funcs.iter().any(|(n, _)| n == name)
+            || self.globals.iter().any(|(n, _, _)| n == name)
+            || self.memories.iter().any(|(n, _)| n == name)
+            || self.tables.iter().any(|(n, _)| n == name)
+            || self.reexports.iter().any(|(n, _)| n == name)
+    }
+
+    pub fn global(
+        &mut self,
+        name: &str,
+        value: RuntimeValue,
+        mutable: bool,
+    ) -> &mut HostModuleBuilder {
+        self.globals.push((name.to_owned(), value, mutable));
+        self
+    }
+
+    pub fn memory(&mut self, name: &str, ty: MemoryType) -> &mut HostModuleBuilder {
+        self.memories.push((name.to_owned(), ty));
+        self
+    }
+
+    pub fn table(&mut self, name: &str, ty: TableType) -> &mut HostModuleBuilder {
+        self.tables.push((name.to_owned(), ty));
+        self
+    }
+
+    /// Attaches `state` to the module. The returned `Rc` can be captured by
+    /// the module functions; the state is also available from the built
+    /// instance via `InstanceToken::host_state`.
+    pub fn state<T: Any>(&mut self, state: T) -> Rc<T> {
+        let state = Rc::new(state);
+        self.state = Some(state.clone());
+        state
+    }
+
+    pub fn build(&self) -> InstanceToken {
+        let mut module = Module::new();
+        let mut dependencies = HashSet::new();
+        let mut contexts = HashSet::new();
+        let mut function_imports = PrimaryMap::new();
+        let tunables = Tunables::default();
+
+        for (name, func) in &self.funcs {
+            let sig = module.signatures.push(func.signature().clone());
+            let index = module.functions.push(sig);
+            module
+                .imported_funcs
+                .push((String::new(), name.clone()));
+            module
+                .exports
+                .insert(name.clone(), Export::Function(index));
+
+            let (vmctx, body) = func.vmctx_and_body();
+            function_imports.push(VMFunctionImport { body, vmctx });
+            dependencies.insert(func.instance().handle().clone());
+            contexts.extend(func.instance().contexts().clone());
+        }
+
+        // Imported entities precede the defined ones in the index spaces.
+        let mut table_imports = PrimaryMap::new();
+        let mut memory_imports = PrimaryMap::new();
+        let mut global_imports = PrimaryMap::new();
+        for (name, export) in &self.reexports {
+            let field = (String::new(), name.clone());
+            match *export {
+                wasmtime_runtime::Export::Table {
+                    definition,
+                    vmctx,
+                    ref table,
+                } => {
+                    let index = module.table_plans.push(table.clone());
+                    module.imported_tables.push(field);
+                    module.exports.insert(name.clone(), Export::Table(index));
+                    table_imports.push(VMTableImport {
+                        from: definition,
+                        vmctx,
+                    });
+                }
+                wasmtime_runtime::Export::Memory {
+                    definition,
+                    vmctx,
+                    ref memory,
+                } => {
+                    let index = module.memory_plans.push(memory.clone());
+                    module.imported_memories.push(field);
+                    module.exports.insert(name.clone(), Export::Memory(index));
+                    memory_imports.push(VMMemoryImport {
+                        from: definition,
+                        vmctx,
+                    });
+                }
+                wasmtime_runtime::Export::Global {
+                    definition,
+                    ref global,
+                } => {
+                    let index = module.globals.push(Global {
+                        initializer: GlobalInit::Import,
+                        ..global.clone()
+                    });
+                    module.imported_globals.push(field);
+                    module.exports.insert(name.clone(), Export::Global(index));
+                    global_imports.push(VMGlobalImport { from: definition });
+                }
+                wasmtime_runtime::Export::Function { .. } => unreachable!(),
+            }
+        }
+        for instance in &self.dependencies {
+            dependencies.insert(instance.handle().clone());
+            contexts.extend(instance.contexts().clone());
+        }
+
+        for (name, value, mutable) in &self.globals {
+            let initializer = match *value {
+                RuntimeValue::I32(i) => GlobalInit::I32Const(i),
+                RuntimeValue::I64(i) => GlobalInit::I64Const(i),
+                RuntimeValue::F32(f) => GlobalInit::F32Const(f),
+                RuntimeValue::F64(f) => GlobalInit::F64Const(f),
+            };
+            let index = module.globals.push(Global {
+                ty: value.value_type(),
+                mutability: *mutable,
+                initializer,
+            });
+            module.exports.insert(name.clone(), Export::Global(index));
+        }
+
+        for (name, ty) in &self.memories {
+            let index = module
+                .memory_plans
+                .push(MemoryPlan::for_memory(ty.memory(), &tunables));
+            module.exports.insert(name.clone(), Export::Memory(index));
+        }
+
+        for (name, ty) in &self.tables {
+            let index = module
+                .table_plans
+                .push(TablePlan::for_table(ty.table(), &tunables));
+            module.exports.insert(name.clone(), Export::Table(index));
+        }
+
+        let imports = Imports::new(
+            dependencies,
+            function_imports,
+            table_imports,
+            memory_imports,
+            global_imports,
+        );
+        InstanceToken::from_imports(module, imports, Box::new(self.state.clone()), contexts)
+    }
+}
@@ -0,0 +1,319 @@
+use crate::module_info::ModuleParseError;
+use crate::types::{FuncType, ValType};
+use cranelift_codegen::ir;
+use std::collections::HashMap;
+use std::fmt;
+use wasmparser::{ImportSectionEntryType, ModuleReader, ResizableLimits, SectionCode, Type};
+use wasmtime_runtime::{Export, InstanceHandle};
+
+/// Import of a module that is missing or has incompatible type.
+#[derive(Debug, Clone)]
+pub struct ImportError {
+    pub module: String,
+    pub field: String,
+    /// Type of the import, e.g. "func (i32) -> ()".
+    pub expected: String,
+    /// Type of the provided definition, `None` if nothing was provided.
+    pub provided: Option<String>,
+    /// Close names of the registered modules or of their exports.
+    pub suggestions: Vec<String>,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}::{}: expected {}, ", self.module, self.field, self.expected)?;
+        match self.provided {
+            Some(ref provided) => write!(f, "found {}", provided)?,
+            None => write!(f, "not provided")?,
+        }
+        if !self.suggestions.is_empty() {
+            write!(f, " (did you mean {}?)", self.suggestions.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// All imports of a module that cannot be satisfied.
+#[derive(Fail, Debug)]
+pub struct LinkError(pub Vec<ImportError>);
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Failed to link module:")?;
+        for error in &self.0 {
+            write!(f, "\n  {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+fn type_name(ty: Type) -> String {
+    match ty {
+        Type::I32 => "i32".to_owned(),
+        Type::I64 => "i64".to_owned(),
+        Type::F32 => "f32".to_owned(),
+        Type::F64 => "f64".to_owned(),
+        _ => format!("{:?}", ty).to_lowercase(),
+    }
+}
+
+fn val_type(ty: Type) -> Option<ValType> {
+    match ty {
+        Type::I32 => Some(ValType::I32),
+        Type::I64 => Some(ValType::I64),
+        Type::F32 => Some(ValType::F32),
+        Type::F64 => Some(ValType::F64),
+        _ => None,
+    }
+}
+
+fn func_type(ty: &wasmparser::FuncType) -> Option<FuncType> {
+    let params: Option<Vec<_>> = ty.params.iter().cloned().map(val_type).collect();
+    let results: Option<Vec<_>> = ty.returns.iter().cloned().map(val_type).collect();
+    Some(FuncType::new(params?, results?))
+}
+
+fn limits_name(minimum: u32, maximum: Option<u32>) -> String {
+    match maximum {
+        Some(maximum) => format!("{}..{}", minimum, maximum),
+        None => format!("{}..", minimum),
+    }
+}
+
+fn limits_match(expected: &ResizableLimits, minimum: u32, maximum: Option<u32>) -> bool {
+    minimum >= expected.initial
+        && match (expected.maximum, maximum) {
+            (None, _) => true,
+            (Some(expected), Some(maximum)) => maximum <= expected,
+            (Some(_), None) => false,
+        }
+}
+
+fn describe_export(export: &Export) -> String {
+    match export {
+        Export::Function { signature, .. } => match FuncType::from_signature(signature) {
+            Some(ty) => format!("func {}", ty),
+            None => format!("func {}", signature),
+        },
+        Export::Table { table, .. } => format!(
+            "table {}",
+            limits_name(table.table.minimum, table.table.maximum)
+        ),
+        Export::Memory { memory, .. } => format!(
+            "memory {}",
+            limits_name(memory.memory.minimum, memory.memory.maximum)
+        ),
+        Export::Global { global, .. } => global_name(global.ty, global.mutability),
+    }
+}
+
+fn global_name(ty: ir::Type, mutable: bool) -> String {
+    let ty = ValType::from_ir_type(ty).map_or_else(|| ty.to_string(), |ty| ty.to_string());
+    if mutable {
+        format!("global (mut {})", ty)
+    } else {
+        format!("global {}", ty)
+    }
+}
+
+/// Returns description of the import type `ty` and whether `export` matches
+/// it.
+fn check_import(
+    ty: &ImportSectionEntryType,
+    types: &[wasmparser::FuncType],
+    export: Option<&Export>,
+) -> (String, bool) {
+    match *ty {
+        ImportSectionEntryType::Function(index) => {
+            let func_ty = match types.get(index as usize) {
+                Some(func_ty) => func_ty,
+                None => return ("func".to_owned(), false),
+            };
+            match func_type(func_ty) {
+                Some(ty) => {
+                    let matches = match export {
+                        Some(Export::Function { signature, .. }) => *signature == ty.signature(),
+                        _ => false,
+                    };
+                    (format!("func {}", ty), matches)
+                }
+                // Signatures with types the host cannot represent are never
+                // satisfied, rather than trusting any function to match.
+                None => {
+                    let params: Vec<_> = func_ty.params.iter().cloned().map(type_name).collect();
+                    let results: Vec<_> =
+                        func_ty.returns.iter().cloned().map(type_name).collect();
+                    let description = format!(
+                        "func ({}) -> ({}), which is not supported",
+                        params.join(", "),
+                        results.join(", ")
+                    );
+                    (description, false)
+                }
+            }
+        }
+        ImportSectionEntryType::Table(ref table) => {
+            let limits = &table.limits;
+            let matches = match export {
+                Some(Export::Table { table, .. }) => {
+                    limits_match(limits, table.table.minimum, table.table.maximum)
+                }
+                _ => false,
+            };
+            (format!("table {}", limits_name(limits.initial, limits.maximum)), matches)
+        }
+        ImportSectionEntryType::Memory(ref memory) => {
+            let limits = &memory.limits;
+            let matches = match export {
+                Some(Export::Memory { memory, .. }) => {
+                    limits_match(limits, memory.memory.minimum, memory.memory.maximum)
+                }
+                _ => false,
+            };
+            (format!("memory {}", limits_name(limits.initial, limits.maximum)), matches)
+        }
+        ImportSectionEntryType::Global(ref global) => {
+            let description = match val_type(global.content_type) {
+                Some(ty) if global.mutable => format!("global (mut {})", ty),
+                Some(ty) => format!("global {}", ty),
+                None => format!("global {}", type_name(global.content_type)),
+            };
+            let matches = match (export, val_type(global.content_type)) {
+                (Some(Export::Global { global: g, .. }), Some(ty)) => {
+                    ValType::from_ir_type(g.ty) == Some(ty) && g.mutability == global.mutable
+                }
+                _ => false,
+            };
+            (description, matches)
+        }
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for j in 0..b.len() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == b[j] {
+                previous
+            } else {
+                1 + previous.min(row[j]).min(row[j + 1])
+            };
+            previous = current;
+        }
+    }
+    row[b.len()]
+}
+
+fn suggestions<'a, I: Iterator<Item = &'a String>>(name: &str, candidates: I) -> Vec<String> {
+    let limit = (name.chars().count() / 3).max(1);
+    let mut close: Vec<(usize, String)> = candidates
+        .map(|candidate| (edit_distance(name, candidate), candidate.clone()))
+        .filter(|&(distance, _)| distance <= limit)
+        .collect();
+    close.sort();
+    close.into_iter().map(|(_, candidate)| candidate).collect()
+}
+
+/// Imports declared by a module, with the types they refer to.
+pub(crate) struct ModuleImports {
+    types: Vec<wasmparser::FuncType>,
+    imports: Vec<(String, String, ImportSectionEntryType)>,
+}
+
+impl ModuleImports {
+    pub fn parse(data: &[u8]) -> Result<ModuleImports, ModuleParseError> {
+        let mut types = Vec::new();
+        let mut imports = Vec::new();
+        let mut reader = ModuleReader::new(data)?;
+        while !reader.eof() {
+            let section = reader.read()?;
+            match section.code {
+                SectionCode::Type => {
+                    for ty in section.get_type_section_reader()? {
+                        types.push(ty?);
+                    }
+                }
+                SectionCode::Import => {
+                    for entry in section.get_import_section_reader()? {
+                        let entry = entry?;
+                        imports.push((entry.module.to_owned(), entry.field.to_owned(), entry.ty));
+                    }
+                }
+                _ => (),
+            }
+        }
+        Ok(ModuleImports { types, imports })
+    }
+
+    /// Returns names of the imported modules, without duplicates.
+    pub fn modules(&self) -> Vec<&str> {
+        let mut modules: Vec<&str> = Vec::new();
+        for (module, _, _) in &self.imports {
+            if !modules.contains(&module.as_str()) {
+                modules.push(module.as_str());
+            }
+        }
+        modules
+    }
+
+    /// Returns function imports, which the `instances` do not provide, with
+    /// their types.
+    pub fn missing_funcs(
+        &self,
+        instances: &HashMap<String, InstanceHandle>,
+    ) -> Vec<(&str, &str, FuncType)> {
+        self.imports
+            .iter()
+            .filter_map(|(module, field, ty)| {
+                let index = match *ty {
+                    ImportSectionEntryType::Function(index) => index,
+                    _ => return None,
+                };
+                let provided = instances
+                    .get(module)
+                    .map_or(false, |instance| instance.module_ref().exports.contains_key(field));
+                if provided {
+                    return None;
+                }
+                let ty = func_type(self.types.get(index as usize)?)?;
+                Some((module.as_str(), field.as_str(), ty))
+            })
+            .collect()
+    }
+
+    /// Checks all imports against the `instances` by module name, reporting
+    /// every missing or mismatched one.
+    pub fn check(&self, instances: &HashMap<String, InstanceHandle>) -> Result<(), LinkError> {
+        let mut errors = Vec::new();
+        for (module, field, ty) in &self.imports {
+            let instance = instances.get(module);
+            let export = instance.and_then(|instance| instance.clone().lookup(field));
+            let (expected, matches) = check_import(ty, &self.types, export.as_ref());
+            if matches {
+                continue;
+            }
+            let suggestions = match instance {
+                Some(instance) if export.is_none() => {
+                    suggestions(field, instance.module_ref().exports.keys())
+                }
+                Some(_) => Vec::new(),
+                None => suggestions(module, instances.keys()),
+            };
+            errors.push(ImportError {
+                module: module.clone(),
+                field: field.clone(),
+                expected,
+                provided: export.as_ref().map(describe_export),
+                suggestions,
+            });
+        }
+        if !errors.is_empty() {
+            return Err(LinkError(errors));
+        }
+        Ok(())
+    }
+}
@@ -1,12 +1,18 @@
 use crate::context::{create_context, ContextToken};
-use cranelift_codegen::ir;
+use crate::fuel::{Fuel, FuelNotEnabled};
+use crate::interrupt::InterruptHandle;
+use crate::module_info::ModuleInfo;
+use crate::store::{enter_store, LiveGuard, LiveKind, StoreGuard};
+use crate::trap::{enter_wasm, take_trap, EntryGuard, FrameInfo, Trap};
+use crate::types::FuncType;
 use failure::Error;
-use std::collections::HashSet;
-use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
 use wasmtime_jit::{ActionOutcome, RuntimeValue};
 use wasmtime_runtime::{Imports, Export, InstanceHandle, VMContext, VMFunctionBody};
 use std::any::Any;
-use cranelift_entity::{PrimaryMap, BoxedSlice};
+use cranelift_entity::{EntityRef, PrimaryMap, BoxedSlice};
 use cranelift_wasm::DefinedFuncIndex;
 use wasmtime_environ::Module;
 
@@ -16,6 +22,18 @@ pub struct InstanceToken {
 
     // We need to keep CodeMemory alive.
     contexts: HashSet<ContextToken>,
+
+    fuel: Option<Fuel>,
+    interrupt: Option<InterruptHandle>,
+
+    module_info: Option<Rc<ModuleInfo>>,
+
+    // Data of the store owning the instance, entered with its exports.
+    store_data: Option<Weak<dyn Any>>,
+
+    max_call_depth: Option<usize>,
+
+    _live: Rc<LiveGuard>,
 }
 
 impl InstanceToken {
@@ -31,6 +49,12 @@ impl InstanceToken {
         InstanceToken {
             instance_handle,
             contexts,
+            fuel: None,
+            interrupt: None,
+            module_info: None,
+            store_data: None,
+            max_call_depth: None,
+            _live: Rc::new(LiveGuard::new(LiveKind::Instance, 1)),
         }
     }
 
@@ -38,23 +62,136 @@ impl InstanceToken {
         InstanceToken {
             instance_handle: handle,
             contexts: HashSet::new(),
+            fuel: None,
+            interrupt: None,
+            module_info: None,
+            store_data: None,
+            max_call_depth: None,
+            _live: Rc::new(LiveGuard::new(LiveKind::Instance, 1)),
+        }
+    }
+
+    pub(crate) fn with_metering(
+        mut self,
+        fuel: Option<Fuel>,
+        interrupt: Option<InterruptHandle>,
+    ) -> InstanceToken {
+        self.fuel = fuel;
+        self.interrupt = interrupt;
+        self
+    }
+
+    pub(crate) fn with_module_info(mut self, module_info: Rc<ModuleInfo>) -> InstanceToken {
+        self.module_info = Some(module_info);
+        self
+    }
+
+    pub(crate) fn with_max_call_depth(mut self, depth: usize) -> InstanceToken {
+        self.max_call_depth = Some(depth);
+        self
+    }
+
+    pub(crate) fn with_store_data(mut self, data: Weak<dyn Any>) -> InstanceToken {
+        self.store_data = Some(data);
+        self
+    }
+
+    /// Registers the host call of exported function `name` for trap frames
+    /// and makes the instance store data current. Fails if the call would
+    /// exceed `Config::max_call_depth`.
+    pub(crate) fn enter_export(&self, name: &str) -> Result<(EntryGuard, StoreGuard), Trap> {
+        let frame = match self.instance_handle.module_ref().exports.get(name) {
+            Some(wasmtime_environ::Export::Function(index)) => {
+                let index = index.index() as u32;
+                Some(match self.module_info {
+                    Some(ref info) => info.frame_info(info.original_index(index), None),
+                    None => FrameInfo::new(index, None, None, None),
+                })
+            }
+            _ => None,
+        };
+        let entry = enter_wasm(self.module_info.clone(), frame, self.max_call_depth)?;
+        Ok((entry, self.enter_store()))
+    }
+
+    /// Makes the data of the store owning the instance available to the
+    /// host functions until the guard is dropped, e.g. around a direct call
+    /// of an export.
+    pub(crate) fn enter_store(&self) -> StoreGuard {
+        enter_store(self.store_data.clone())
+    }
+
+    /// Adds `fuel` to the instance created with `Config::consume_fuel`.
+    pub fn add_fuel(&self, fuel: u64) -> Result<(), Error> {
+        match self.fuel {
+            Some(ref f) => {
+                f.add(fuel);
+                Ok(())
+            }
+            None => Err(FuelNotEnabled.into()),
         }
     }
 
+    /// Returns fuel left to the instance, or `None` if the instance does
+    /// not consume fuel.
+    pub fn fuel_remaining(&self) -> Option<u64> {
+        self.fuel.as_ref().map(Fuel::remaining)
+    }
+
+    /// Returns handle to interrupt the instance created with
+    /// `Config::interruptable`.
+    pub fn interrupt_handle(&self) -> Option<InterruptHandle> {
+        self.interrupt.clone()
+    }
+
     pub fn from_raw_parts(
         module: Module, 
         finished_functions: BoxedSlice<DefinedFuncIndex, *const VMFunctionBody>,
         state: Box<dyn Any>
     ) -> InstanceToken {
-        let imports = Imports::none();
+        InstanceToken::from_module(module, finished_functions, Imports::none(), state, HashSet::new())
+    }
+
+    /// Creates instance of a module that has no definitions of its own and
+    /// only re-exports `imports`, e.g. to combine host functions from
+    /// different instances into a single import module.
+    pub(crate) fn from_imports(
+        module: Module,
+        imports: Imports,
+        state: Box<dyn Any>,
+        contexts: HashSet<ContextToken>,
+    ) -> InstanceToken {
+        InstanceToken::from_module(
+            module,
+            PrimaryMap::new().into_boxed_slice(),
+            imports,
+            state,
+            contexts,
+        )
+    }
+
+    /// Returns state attached by `HostModuleBuilder::state`, if it has type `T`.
+    pub fn host_state<T: Any>(&self) -> Option<Rc<T>> {
+        let mut handle = self.instance_handle.clone();
+        let state = handle
+            .host_state()
+            .downcast_ref::<Option<Rc<dyn Any>>>()?
+            .clone()?;
+        state.downcast::<T>().ok()
+    }
+
+    fn from_module(
+        module: Module,
+        finished_functions: BoxedSlice<DefinedFuncIndex, *const VMFunctionBody>,
+        imports: Imports,
+        state: Box<dyn Any>,
+        contexts: HashSet<ContextToken>,
+    ) -> InstanceToken {
         let data_initializers = Vec::new();
         let signatures = PrimaryMap::new();
 
-        let mut context = ContextToken::create();
-        let global_exports = context.context().get_global_exports();
-
-        let mut contexts = HashSet::new();
-        contexts.insert(context);
+        // The instance has no code of its own, so it does not need a context.
+        let global_exports = Rc::new(RefCell::new(HashMap::new()));
 
         InstanceToken::new(
             InstanceHandle::new(
@@ -80,10 +217,6 @@ pub struct CallableExportNotFound(String);
 #[fail(display = "Incompatible type for exported call {}: {}", _0, _1)]
 pub struct CallableExportNotValidForSig(String, String);
 
-#[derive(Fail, Debug)]
-#[fail(display = "Trap from within function {}: {}", _0, _1)]
-pub struct TrappedInvoke(String, String);
-
 #[derive(Clone)]
 pub struct InstanceExport {
     instance: InstanceToken,
@@ -92,15 +225,14 @@ pub struct InstanceExport {
 
 impl InstanceExport {
     pub fn invoke(&self, args: &[RuntimeValue]) -> Result<Vec<RuntimeValue>, Error> {
-        let mut context = create_context();
+        let mut context = create_context(false);
         let mut instance = self.instance.instance_handle.clone();
+        let _entry = self.instance.enter_export(&self.export_name)?;
         Ok(
             match context.invoke(&mut instance, &self.export_name, args)? {
                 ActionOutcome::Returned { values } => values,
                 ActionOutcome::Trapped { message } => {
-                    return Err(
-                        TrappedInvoke(self.export_name.to_owned(), String::from(message)).into(),
-                    );
+                    return Err(take_trap(&message).into());
                 }
             },
         )
@@ -147,7 +279,7 @@ impl InstanceExport {
 
 #[derive(Clone)]
 pub struct InstanceCallableExport {
-    instance: InstanceToken,
+    pub(crate) instance: InstanceToken,
     vmctx: *mut VMContext,
     body: *const VMFunctionBody,
 }
@@ -173,8 +305,9 @@ impl InstanceToken {
     pub fn get_callable_export(
         &self,
         name: &str,
-        sig: ir::Signature,
+        ty: &FuncType,
     ) -> Result<InstanceCallableExport, Error> {
+        let sig = ty.signature();
         let mut instance = self.clone();
         match instance.instance_handle.lookup(name) {
             Some(wasmtime_runtime::Export::Function {
@@ -1,16 +1,87 @@
 use crate::context::ContextToken;
+use crate::fuel::{create_fuel_import, Fuel, FUEL_MODULE};
+use crate::import_check::ModuleImports;
 use crate::imports::ImportSet;
 use crate::instance::InstanceToken;
+use crate::instrument::{instrument, parse};
+use crate::interrupt::InterruptHandle;
+use crate::limits::{check_instantiation, create_limits_import, memory_maximums, LIMITS_MODULE};
+use crate::module_info::ModuleInfo;
+use crate::perf::{compiled_sizes, write_perf_map};
+use crate::trap::{enter_wasm, take_trap};
+use crate::validate::{check_supported, validate};
+use crate::wat::to_binary;
 use failure::Error;
 use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use wasmtime_jit::{ActionError, Context, SetupError};
+use wasmtime_runtime::{InstanceHandle, InstantiationError};
 
+/// Instantiates module `data`, given in the binary or in the text format.
 pub fn instantiate_in_context(
     data: &[u8],
     imports: HashMap<String, ImportSet>,
     mut context_token: ContextToken,
 ) -> Result<InstanceToken, Error> {
+    let binary = to_binary(data)?;
+    let data = &binary[..];
     let mut contexts = HashSet::new();
-    let instance = {
+    let config = context_token.config().clone();
+    validate(data, &config)?;
+    check_supported(data, &config)?;
+    check_imports(data, &imports, &mut context_token)?;
+
+    let fuel = if config.fuel_enabled() {
+        Some(Fuel::new(config.fuel()))
+    } else {
+        None
+    };
+    let interrupt = if config.interrupts_enabled() {
+        Some(InterruptHandle::new())
+    } else {
+        None
+    };
+
+    let limiter = context_token.limiter();
+    let metering = fuel.is_some() || interrupt.is_some();
+
+    let original = data;
+    let instrumented = metering || limiter.is_some();
+    let instrumented_data;
+    let data = if instrumented {
+        let module = parse(data)?;
+        let mut memory_limits = false;
+        if let Some(limiter) = limiter {
+            check_instantiation(&*limiter, &module, context_token.instance_count())?;
+            let maximums = memory_maximums(&module);
+            if !maximums.is_empty() {
+                let limits_import = create_limits_import(limiter, maximums);
+                context_token
+                    .context()
+                    .name_instance(LIMITS_MODULE.to_owned(), limits_import.handle().clone());
+                contexts.extend(limits_import.contexts().clone());
+                memory_limits = true;
+            }
+        }
+        if metering {
+            let fuel_import = create_fuel_import(fuel.as_ref(), interrupt.as_ref());
+            context_token
+                .context()
+                .name_instance(FUEL_MODULE.to_owned(), fuel_import.handle().clone());
+            contexts.extend(fuel_import.contexts().clone());
+        }
+        instrumented_data = instrument(module, metering, memory_limits)?;
+        &instrumented_data[..]
+    } else {
+        data
+    };
+
+    let mut module_info = ModuleInfo::parse(data)?;
+    let debug_info = context_token.context().debug_info();
+    module_info.load_symbols(original, debug_info && !instrumented);
+    let module_info = Rc::new(module_info);
+
+    let mut instance = {
         let mut context = context_token.context();
 
         for (name, set) in imports {
@@ -22,11 +93,65 @@ pub fn instantiate_in_context(
                 _ => panic!("unsupported ImportSet"),
             }
         }
-        context.instantiate_module(None, &data)?
+        instantiate_module(&mut context, data, &module_info, config.call_depth_limit())?
     };
+    context_token.count_instance();
+    let sizes = compiled_sizes(data, debug_info)?;
+    if config.perf_map_enabled() {
+        write_perf_map(&mut instance, &sizes, &module_info)?;
+    }
+    context_token.add_code_bytes(sizes.iter().sum());
     contexts.insert(context_token);
 
-    Ok(InstanceToken::new(instance, contexts))
+    Ok(InstanceToken::new(instance, contexts)
+        .with_metering(fuel, interrupt)
+        .with_module_info(module_info)
+        .with_max_call_depth(config.call_depth_limit()))
+}
+
+/// Instantiates module `data` in `context`, running its start function as
+/// a call from the host: a trap of the start function, e.g. raised by a
+/// host function it calls, is returned as `Trap`.
+fn instantiate_module(
+    context: &mut Context,
+    data: &[u8],
+    module_info: &Rc<ModuleInfo>,
+    max_call_depth: usize,
+) -> Result<InstanceHandle, Error> {
+    let _entry = enter_wasm(Some(module_info.clone()), None, Some(max_call_depth))?;
+    match context.instantiate_module(None, data) {
+        Ok(instance) => Ok(instance),
+        Err(ActionError::Setup(SetupError::Instantiate(InstantiationError::StartTrap(
+            message,
+        )))) => Err(take_trap(&message).into()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Checks the imports of module `data` against `imports` and the instances
+/// already named in the context, which `wasmtime_jit` would resolve them to.
+fn check_imports(
+    data: &[u8],
+    imports: &HashMap<String, ImportSet>,
+    context_token: &mut ContextToken,
+) -> Result<(), Error> {
+    let module_imports = ModuleImports::parse(data)?;
+    let mut handles = HashMap::new();
+    for (name, set) in imports {
+        if let ImportSet::InstanceExports(i) = set {
+            handles.insert(name.clone(), i.handle().clone());
+        }
+    }
+    let mut context = context_token.context();
+    for module in module_imports.modules() {
+        if !handles.contains_key(module) {
+            if let Ok(instance) = context.get_instance(module) {
+                handles.insert(module.to_owned(), instance.clone());
+            }
+        }
+    }
+    module_imports.check(&handles)?;
+    Ok(())
 }
 
 pub fn instantiate(
@@ -0,0 +1,67 @@
+use crate::fuel::inject_fuel;
+use crate::limits::inject_memory_grow_check;
+use failure::Error;
+use parity_wasm::elements;
+
+#[derive(Fail, Debug)]
+#[fail(display = "Module instrumentation failed: {}", _0)]
+pub struct InstrumentationFailed(pub(crate) String);
+
+/// Rewrites `module` to call into the host for fuel and interrupt checks
+/// (`metering`) and before `memory.grow` (`memory_limits`).
+pub(crate) fn instrument(
+    mut module: elements::Module,
+    metering: bool,
+    memory_limits: bool,
+) -> Result<Vec<u8>, Error> {
+    if memory_limits {
+        module = inject_memory_grow_check(module)?;
+    }
+    if metering {
+        module = inject_fuel(module)?;
+    }
+    Ok(parity_wasm::serialize(module).map_err(|e| InstrumentationFailed(e.to_string()))?)
+}
+
+pub(crate) fn parse(data: &[u8]) -> Result<elements::Module, Error> {
+    Ok(parity_wasm::deserialize_buffer::<elements::Module>(data)
+        .map_err(|e| InstrumentationFailed(e.to_string()))?)
+}
+
+/// Increments references to functions with index `>= inserted` after a
+/// function import was inserted at `inserted`.
+pub(crate) fn shift_function_indices(module: &mut elements::Module, inserted: u32) {
+    let shift = |index: &mut u32| {
+        if *index >= inserted {
+            *index += 1;
+        }
+    };
+
+    if let Some(code_section) = module.code_section_mut() {
+        for body in code_section.bodies_mut() {
+            for instruction in body.code_mut().elements_mut() {
+                if let elements::Instruction::Call(ref mut index) = *instruction {
+                    shift(index);
+                }
+            }
+        }
+    }
+    if let Some(export_section) = module.export_section_mut() {
+        for export in export_section.entries_mut() {
+            if let elements::Internal::Function(ref mut index) = *export.internal_mut() {
+                shift(index);
+            }
+        }
+    }
+    if let Some(elements_section) = module.elements_section_mut() {
+        for segment in elements_section.entries_mut() {
+            for index in segment.members_mut() {
+                shift(index);
+            }
+        }
+    }
+    if let Some(mut start) = module.start_section() {
+        shift(&mut start);
+        module.set_start_section(start);
+    }
+}
@@ -0,0 +1,28 @@
+use crate::trap::{Trap, TrapCode};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Interrupts wasm code running in an instance created with
+/// `Config::interruptable`. Can be sent to and triggered from another thread.
+#[derive(Clone)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    pub(crate) fn new() -> InterruptHandle {
+        InterruptHandle(Arc::new(AtomicBool::new(false)))
+    }
+
+    /// Makes the running wasm code trap with `TrapCode::Interrupt` at the
+    /// next function entry or loop iteration. If no wasm code is running,
+    /// the next call into the instance is interrupted instead.
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub(crate) fn check(&self) -> Result<(), Trap> {
+        if self.0.swap(false, Ordering::SeqCst) {
+            return Err(Trap::with_code(TrapCode::Interrupt, "interrupted"));
+        }
+        Ok(())
+    }
+}
@@ -28,21 +28,69 @@
 #[macro_use]
 extern crate failure_derive;
 
+mod command;
+mod config;
 mod context;
+mod fuel;
+mod func;
+mod host_module;
+mod import_check;
 mod imports;
 mod instance;
 mod instantiate;
+mod instrument;
+mod interrupt;
+mod limits;
+mod linker;
+mod module_info;
+mod perf;
+mod scratch_fs;
+mod store;
+mod trampoline;
+mod trap;
+mod typed_func;
+mod types;
+mod validate;
 mod wasi;
+mod wasi_hooks;
+mod wasi_policy;
+mod wat;
 
 pub mod extra;
 
+pub use crate::command::{
+    instantiate_reactor, instantiate_reactor_in_context, run_command, run_command_in_context,
+    AmbiguousWasiModule, ExitStatus, ExitedDuringInitialize, NotAReactor,
+};
+pub use crate::config::Config;
 pub use crate::context::ContextToken;
+pub use crate::fuel::FuelNotEnabled;
+pub use crate::func::{Func, IntoFunc, IntoFuncWithData, WasmRet, WasmTy};
+pub use crate::host_module::HostModuleBuilder;
+pub use crate::import_check::{ImportError, LinkError};
 pub use crate::imports::{Import, ImportSet};
 pub use crate::instance::{InstanceCallableExport, InstanceExport, InstanceToken};
 pub use crate::instantiate::{instantiate, instantiate_in_context};
-pub use crate::wasi::create_wasi;
+pub use crate::instrument::InstrumentationFailed;
+pub use crate::interrupt::InterruptHandle;
+pub use crate::limits::{ResourceLimitExceeded, ResourceLimiter};
+pub use crate::linker::{InstanceAlreadyDefined, Linker};
+pub use crate::module_info::ModuleParseError;
+pub use crate::scratch_fs::ScratchFs;
+pub use crate::store::{live_counts, with_store_data, LiveCounts, Store};
+pub use crate::trap::{FrameInfo, Trap, TrapCode};
+pub use crate::typed_func::{TypedFunc, WasmParams};
+pub use crate::types::{FuncType, MemoryType, TableType, ValType};
+pub use crate::validate::{validate, UnsupportedFeature, ValidationError};
+pub use crate::wasi::{OutputBuffer, WasiConfig, WasiError};
+pub use crate::wasi_hooks::{ClockId, VirtualClock, WasiClock};
+pub use crate::wasi_policy::{DenialReason, DirRights, WasiCategory, WasiDenial};
+pub use crate::wat::{wat2wasm, WatError};
 pub use wasmtime_jit::RuntimeValue;
 
+/// Implemented by `#[wasm_export]` for a trait of exported functions. The
+/// trait methods cannot return a trap, so a trapping call panics with the
+/// `Trap` as the payload.
 pub trait WasmExport {
     type Concrete;
     fn export(i: InstanceToken) -> Self::Concrete;
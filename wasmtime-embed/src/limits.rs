@@ -0,0 +1,219 @@
+use crate::func::Func;
+use crate::host_module::HostModuleBuilder;
+use crate::instance::InstanceToken;
+use crate::instrument::{shift_function_indices, InstrumentationFailed};
+use crate::types::{FuncType, ValType};
+use failure::Error;
+use parity_wasm::builder;
+use parity_wasm::elements::{self, BlockType, Instruction, Instructions, ValueType};
+use std::rc::Rc;
+use wasmtime_jit::RuntimeValue;
+
+/// Name of the import module that lets instrumented modules consult the
+/// `ResourceLimiter` before growing memory.
+pub(crate) const LIMITS_MODULE: &str = "__wasmtime_embed_limits";
+
+const MEMORY_GROW_FUNC: &str = "memory_grow";
+
+/// Vetoes resource allocation by instances, see `ContextToken::set_limiter`.
+///
+/// Sizes are in wasm pages for memories and in elements for tables.
+pub trait ResourceLimiter {
+    /// Called when an instance creates a memory (with `current` 0) and
+    /// before `memory.grow`. Returning `false` fails the instantiation or
+    /// makes `memory.grow` return -1.
+    fn memory_growing(&self, current: u32, desired: u32, maximum: Option<u32>) -> bool;
+
+    /// Called when an instance creates a table (with `current` 0).
+    /// Returning `false` fails the instantiation.
+    fn table_growing(&self, _current: u32, _desired: u32, _maximum: Option<u32>) -> bool {
+        true
+    }
+
+    /// Maximum number of instances successfully created in the context.
+    fn instances(&self) -> usize {
+        usize::max_value()
+    }
+}
+
+#[derive(Fail, Debug)]
+#[fail(display = "Resource limit exceeded: {}", _0)]
+pub struct ResourceLimitExceeded(String);
+
+/// Checks the instance `module` is allowed to be created in a context, which
+/// has `instance_count` instances already.
+pub(crate) fn check_instantiation(
+    limiter: &dyn ResourceLimiter,
+    module: &elements::Module,
+    instance_count: usize,
+) -> Result<(), Error> {
+    if instance_count >= limiter.instances() {
+        return Err(ResourceLimitExceeded(format!(
+            "more than {} instances",
+            limiter.instances()
+        ))
+        .into());
+    }
+    if let Some(memory_section) = module.memory_section() {
+        for memory in memory_section.entries() {
+            let limits = memory.limits();
+            if !limiter.memory_growing(0, limits.initial(), limits.maximum()) {
+                return Err(ResourceLimitExceeded(format!(
+                    "memory of {} pages",
+                    limits.initial()
+                ))
+                .into());
+            }
+        }
+    }
+    if let Some(table_section) = module.table_section() {
+        for table in table_section.entries() {
+            let limits = table.limits();
+            if !limiter.table_growing(0, limits.initial(), limits.maximum()) {
+                return Err(ResourceLimitExceeded(format!(
+                    "table of {} elements",
+                    limits.initial()
+                ))
+                .into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Maximums of the module memories, imported ones first as in the memory
+/// index space.
+pub(crate) fn memory_maximums(module: &elements::Module) -> Vec<Option<u32>> {
+    let imported = module.import_section().map_or(Vec::new(), |section| {
+        section
+            .entries()
+            .iter()
+            .filter_map(|entry| match *entry.external() {
+                elements::External::Memory(ref memory) => Some(memory.limits().maximum()),
+                _ => None,
+            })
+            .collect()
+    });
+    let defined = module.memory_section().map_or(Vec::new(), |section| {
+        section
+            .entries()
+            .iter()
+            .map(|memory| memory.limits().maximum())
+            .collect()
+    });
+    imported.into_iter().chain(defined).collect()
+}
+
+/// Replaces `memory.grow` of every memory with a call to a function that
+/// performs it only if `LIMITS_MODULE.memory_grow(memory, current, delta)`
+/// permits.
+pub(crate) fn inject_memory_grow_check(
+    module: elements::Module,
+) -> Result<elements::Module, Error> {
+    let memories = memory_maximums(&module).len();
+    if memories == 0 {
+        return Ok(module);
+    }
+    if memories > usize::from(u8::max_value()) + 1 {
+        return Err(InstrumentationFailed(format!("{} memories", memories)).into());
+    }
+
+    let mut mbuilder = builder::from_module(module);
+    let check_sig = mbuilder.push_signature(
+        builder::signature()
+            .with_params(vec![ValueType::I32, ValueType::I32, ValueType::I32])
+            .with_return_type(Some(ValueType::I32))
+            .build_sig(),
+    );
+    mbuilder.push_import(
+        builder::import()
+            .module(LIMITS_MODULE)
+            .field(MEMORY_GROW_FUNC)
+            .external()
+            .func(check_sig)
+            .build(),
+    );
+    let mut module = mbuilder.build();
+    let check_func = module.import_count(elements::ImportCountType::Function) as u32 - 1;
+    shift_function_indices(&mut module, check_func);
+
+    let original_bodies = module
+        .code_section()
+        .map_or(0, |section| section.bodies().len());
+
+    // For each memory $m:
+    // (func $grow (param $delta i32) (result i32)
+    //   (if (result i32) (call $check (i32.const $m) (memory.size $m) (local.get $delta))
+    //     (then (memory.grow $m (local.get $delta)))
+    //     (else (i32.const -1))))
+    let mut mbuilder = builder::from_module(module);
+    for memory in 0..memories {
+        let memory = memory as u8;
+        mbuilder.push_function(
+            builder::function()
+                .signature()
+                .with_param(ValueType::I32)
+                .with_return_type(Some(ValueType::I32))
+                .build()
+                .body()
+                .with_instructions(Instructions::new(vec![
+                    Instruction::I32Const(i32::from(memory)),
+                    Instruction::CurrentMemory(memory),
+                    Instruction::GetLocal(0),
+                    Instruction::Call(check_func),
+                    Instruction::If(BlockType::Value(ValueType::I32)),
+                    Instruction::GetLocal(0),
+                    Instruction::GrowMemory(memory),
+                    Instruction::Else,
+                    Instruction::I32Const(-1),
+                    Instruction::End,
+                    Instruction::End,
+                ]))
+                .build()
+                .build(),
+        );
+    }
+    let mut module = mbuilder.build();
+    let first_grow_func = module.functions_space() as u32 - memories as u32;
+
+    if let Some(code_section) = module.code_section_mut() {
+        for body in code_section.bodies_mut().iter_mut().take(original_bodies) {
+            for instruction in body.code_mut().elements_mut() {
+                if let Instruction::GrowMemory(memory) = *instruction {
+                    *instruction = Instruction::Call(first_grow_func + u32::from(memory));
+                }
+            }
+        }
+    }
+    Ok(module)
+}
+
+/// Creates the `LIMITS_MODULE` instance for a module with memories of
+/// `maximums`.
+pub(crate) fn create_limits_import(
+    limiter: Rc<dyn ResourceLimiter>,
+    maximums: Vec<Option<u32>>,
+) -> InstanceToken {
+    let ty = FuncType::new(
+        vec![ValType::I32, ValType::I32, ValType::I32],
+        vec![ValType::I32],
+    );
+    let memory_grow = Func::new(ty, move |args| {
+        let i32_arg = |index: usize| match args[index] {
+            RuntimeValue::I32(value) => value as u32,
+            _ => unreachable!(),
+        };
+        let (memory, current, delta) = (i32_arg(0) as usize, i32_arg(1), i32_arg(2));
+        let maximum = maximums.get(memory).cloned().unwrap_or(None);
+        let allowed = match current.checked_add(delta) {
+            // `memory.grow` fails by itself, nothing to veto.
+            None => true,
+            Some(desired) if maximum.map_or(false, |max| desired > max) => true,
+            Some(desired) => limiter.memory_growing(current, desired, maximum),
+        };
+        Ok(vec![RuntimeValue::I32(allowed as i32)])
+    });
+    HostModuleBuilder::new()
+        .func(MEMORY_GROW_FUNC, memory_grow)
+        .build()
+}
@@ -0,0 +1,131 @@
+use crate::context::ContextToken;
+use crate::func::Func;
+use crate::host_module::HostModuleBuilder;
+use crate::import_check::ModuleImports;
+use crate::imports::ImportSet;
+use crate::instance::InstanceToken;
+use crate::instantiate::instantiate_in_context;
+use crate::trap::Trap;
+use crate::wat::to_binary;
+use failure::Error;
+use std::collections::{HashMap, HashSet};
+use wasmtime_runtime::InstanceHandle;
+
+#[derive(Fail, Debug)]
+#[fail(display = "Instance is already defined in the linker: {}", _0)]
+pub struct InstanceAlreadyDefined(String);
+
+fn handles(instances: &HashMap<String, InstanceToken>) -> HashMap<String, InstanceHandle> {
+    instances
+        .iter()
+        .map(|(name, instance)| (name.clone(), instance.handle().clone()))
+        .collect()
+}
+
+/// Registry of named instances, which resolves the imports of the modules
+/// instantiated through it, e.g.
+///
+/// ```ignore
+/// let mut linker = Linker::new(ContextToken::create());
+/// linker.instance("test", callbacks)?;
+/// let gcd = linker.instantiate_named("gcd", &gcd_wasm)?;
+/// let l1 = linker.instantiate(&l1_wasm)?;
+/// ```
+pub struct Linker {
+    context: ContextToken,
+    instances: HashMap<String, InstanceToken>,
+    stub_unresolved: bool,
+}
+
+impl Linker {
+    /// Creates linker instantiating the modules in `context`.
+    pub fn new(context: ContextToken) -> Linker {
+        Linker {
+            context,
+            instances: HashMap::new(),
+            stub_unresolved: false,
+        }
+    }
+
+    /// Satisfies the function imports the linker cannot resolve with stubs,
+    /// which trap with "unimplemented import module.field" when called.
+    /// The other exports of an instance missing some of the functions are
+    /// still resolved from it.
+    pub fn stub_unresolved_imports(&mut self, enable: bool) -> &mut Linker {
+        self.stub_unresolved = enable;
+        self
+    }
+
+    pub fn context(&self) -> &ContextToken {
+        &self.context
+    }
+
+    /// Registers `instance`, e.g. built by `HostModuleBuilder`, as module
+    /// `name` for the imports.
+    pub fn instance(&mut self, name: &str, instance: InstanceToken) -> Result<&mut Linker, Error> {
+        if self.instances.contains_key(name) {
+            return Err(InstanceAlreadyDefined(name.to_owned()).into());
+        }
+        self.instances.insert(name.to_owned(), instance);
+        Ok(self)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&InstanceToken> {
+        self.instances.get(name)
+    }
+
+    /// Resolves imports of module `data`, reporting all missing or
+    /// mismatched ones as `LinkError`.
+    pub fn resolve(&self, data: &[u8]) -> Result<HashMap<String, ImportSet>, Error> {
+        let data = to_binary(data)?;
+        let module_imports = ModuleImports::parse(&data)?;
+        let mut instances = self.instances.clone();
+        if self.stub_unresolved {
+            let provided = handles(&instances);
+            let mut stubbed = HashSet::new();
+            let mut stubs: HashMap<&str, HostModuleBuilder> = HashMap::new();
+            for (module, field, ty) in module_imports.missing_funcs(&provided) {
+                if !stubbed.insert((module, field)) {
+                    continue;
+                }
+                let message = format!("unimplemented import {}.{}", module, field);
+                stubs
+                    .entry(module)
+                    .or_insert_with(HostModuleBuilder::new)
+                    .func(field, Func::new(ty, move |_| Err(Trap::new(message.clone()))));
+            }
+            for (module, mut builder) in stubs {
+                if let Some(instance) = instances.get(module) {
+                    builder.reexport(instance);
+                }
+                instances.insert(module.to_owned(), builder.build());
+            }
+        }
+        module_imports.check(&handles(&instances))?;
+
+        Ok(module_imports
+            .modules()
+            .into_iter()
+            .map(|module| {
+                let instance = instances[module].clone();
+                (module.to_owned(), ImportSet::InstanceExports(instance))
+            })
+            .collect())
+    }
+
+    /// Instantiates module `data` with the imports resolved by the linker.
+    pub fn instantiate(&self, data: &[u8]) -> Result<InstanceToken, Error> {
+        let imports = self.resolve(data)?;
+        instantiate_in_context(data, imports, self.context.clone())
+    }
+
+    /// Instantiates module `data` and registers it as module `name`.
+    pub fn instantiate_named(&mut self, name: &str, data: &[u8]) -> Result<InstanceToken, Error> {
+        if self.instances.contains_key(name) {
+            return Err(InstanceAlreadyDefined(name.to_owned()).into());
+        }
+        let instance = self.instantiate(data)?;
+        self.instances.insert(name.to_owned(), instance.clone());
+        Ok(instance)
+    }
+}
@@ -0,0 +1,227 @@
+use crate::trap::FrameInfo;
+use failure::Error;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+use wasmparser::{
+    BinaryReaderError, CustomSectionKind, ImportSectionEntryType, ModuleReader, Name,
+    SectionCode,
+};
+
+// Modules of the imports added by instrumentation, see `instrument`.
+const EMBEDDER_MODULE_PREFIX: &str = "__wasmtime_embed_";
+
+#[derive(Fail, Debug)]
+#[fail(display = "Module parsing failed at offset {}: {}", _1, _0)]
+pub struct ModuleParseError(&'static str, usize);
+
+impl From<BinaryReaderError> for ModuleParseError {
+    fn from(e: BinaryReaderError) -> ModuleParseError {
+        ModuleParseError(e.message, e.offset)
+    }
+}
+
+/// Layout of the wasm module an instance was compiled from, used to map
+/// trap locations back to functions.
+///
+/// Instrumentation appends its function imports after the module's own, so
+/// the function indices are adjusted back to the original module. Offsets
+/// refer to the compiled (possibly instrumented) binary.
+pub(crate) struct ModuleInfo {
+    imported_funcs: u32,
+    embedder_imports: u32,
+    // Byte ranges of the function bodies in the module, by defined index.
+    func_ranges: Vec<Range<usize>>,
+    // Function names from the "name" section, by original index.
+    func_names: HashMap<u32, String>,
+    // Rows of the DWARF line programs sorted by module offset; `None` marks
+    // the end of a sequence.
+    lines: Vec<(usize, Option<(Arc<str>, u32)>)>,
+}
+
+impl ModuleInfo {
+    pub fn parse(data: &[u8]) -> Result<ModuleInfo, Error> {
+        Ok(ModuleInfo::parse_sections(data)?)
+    }
+
+    fn parse_sections(data: &[u8]) -> Result<ModuleInfo, ModuleParseError> {
+        let mut imported_funcs = 0;
+        let mut embedder_imports = 0;
+        let mut func_ranges = Vec::new();
+
+        let mut reader = ModuleReader::new(data)?;
+        while !reader.eof() {
+            let section = reader.read()?;
+            match section.code {
+                SectionCode::Import => {
+                    for entry in section.get_import_section_reader()? {
+                        let entry = entry?;
+                        if let ImportSectionEntryType::Function(_) = entry.ty {
+                            if entry.module.starts_with(EMBEDDER_MODULE_PREFIX) {
+                                embedder_imports += 1;
+                            } else {
+                                imported_funcs += 1;
+                            }
+                        }
+                    }
+                }
+                SectionCode::Code => {
+                    for body in section.get_code_section_reader()? {
+                        let reader = body?.get_binary_reader();
+                        let start = reader.original_position();
+                        func_ranges.push(start..start + reader.bytes_remaining());
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(ModuleInfo {
+            imported_funcs,
+            embedder_imports,
+            func_ranges,
+            func_names: HashMap::new(),
+            lines: Vec::new(),
+        })
+    }
+
+    /// Loads function names and, if `dwarf` is set, source lines from the
+    /// original module `data`. The source lines are only valid when `data`
+    /// was compiled as is, i.e. was not instrumented.
+    ///
+    /// Symbols are best effort: broken custom sections are ignored rather
+    /// than failing the instantiation.
+    pub fn load_symbols(&mut self, data: &[u8], dwarf: bool) {
+        let mut debug_sections = HashMap::new();
+        let mut code_start = 0;
+
+        let _ = (|| -> Result<(), ModuleParseError> {
+            let mut reader = ModuleReader::new(data)?;
+            while !reader.eof() {
+                let section = reader.read()?;
+                match section.code {
+                    SectionCode::Code => code_start = section.range().start,
+                    SectionCode::Custom {
+                        kind: CustomSectionKind::Name,
+                        ..
+                    } => self.load_names(section.get_name_section_reader()?)?,
+                    SectionCode::Custom { name, .. } if dwarf && name.starts_with(".debug_") => {
+                        let mut reader = section.get_binary_reader();
+                        let len = reader.bytes_remaining();
+                        debug_sections.insert(name, reader.read_bytes(len)?);
+                    }
+                    _ => (),
+                }
+            }
+            Ok(())
+        })();
+
+        if !debug_sections.is_empty() {
+            let _ = self.load_lines(&debug_sections, code_start);
+        }
+    }
+
+    fn load_names(&mut self, names: wasmparser::NameSectionReader) -> Result<(), ModuleParseError> {
+        for name in names {
+            if let Name::Function(functions) = name? {
+                let mut map = functions.get_map()?;
+                for _ in 0..map.get_count() {
+                    let naming = map.read()?;
+                    self.func_names.insert(naming.index, naming.name.to_owned());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Collects line program rows. Addresses in wasm DWARF are relative to
+    /// the start of the code section payload at `code_start`.
+    fn load_lines(
+        &mut self,
+        sections: &HashMap<&str, &[u8]>,
+        code_start: usize,
+    ) -> Result<(), gimli::Error> {
+        let endian = gimli::LittleEndian;
+        let load = |id: gimli::SectionId| -> Result<_, gimli::Error> {
+            let data = sections.get(id.name()).cloned().unwrap_or(&[]);
+            Ok(gimli::EndianSlice::new(data, endian))
+        };
+        let dwarf = gimli::Dwarf::load(load, |_| Ok(gimli::EndianSlice::new(&[], endian)))?;
+
+        let mut files: HashMap<String, Arc<str>> = HashMap::new();
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+            let program = match unit.line_program {
+                Some(ref program) => program.clone(),
+                None => continue,
+            };
+            let mut rows = program.rows();
+            while let Some((header, row)) = rows.next_row()? {
+                let offset = code_start + row.address() as usize;
+                if row.end_sequence() {
+                    self.lines.push((offset, None));
+                    continue;
+                }
+                let (file, line) = match (row.file(header), row.line()) {
+                    (Some(file), Some(line)) => (file, line as u32),
+                    _ => continue,
+                };
+                let path = dwarf.attr_string(&unit, file.path_name())?;
+                let path = path.to_string_lossy();
+                // The file name alone reads better in a backtrace.
+                let name = path.rsplit('/').next().unwrap_or(&path);
+                let file = files
+                    .entry(name.to_owned())
+                    .or_insert_with(|| Arc::from(name))
+                    .clone();
+                self.lines.push((offset, Some((file, line))));
+            }
+        }
+        self.lines.sort_by_key(|&(offset, _)| offset);
+        Ok(())
+    }
+
+    /// Returns index (in the function index space) of the function, which
+    /// body contains module byte `offset`.
+    pub fn func_index_at(&self, offset: usize) -> Option<u32> {
+        self.func_ranges
+            .iter()
+            .position(|range| range.start <= offset && offset < range.end)
+            .map(|i| self.imported_funcs + i as u32)
+    }
+
+    /// Maps index of a function in the compiled module to the original one.
+    pub fn original_index(&self, index: u32) -> u32 {
+        if index >= self.imported_funcs + self.embedder_imports {
+            index - self.embedder_imports
+        } else {
+            index
+        }
+    }
+
+    /// Function name from the "name" section, by original index.
+    pub fn func_name(&self, index: u32) -> Option<&str> {
+        self.func_names.get(&index).map(String::as_str)
+    }
+
+    fn source_location(&self, offset: usize) -> Option<(Arc<str>, u32)> {
+        let i = match self.lines.binary_search_by_key(&offset, |&(offset, _)| offset) {
+            Ok(i) => i,
+            Err(0) => return None,
+            Err(i) => i - 1,
+        };
+        self.lines[i].1.clone()
+    }
+
+    /// Creates frame of function `func_index` (original index), resolving
+    /// its name and, for known `offset`, its source location.
+    pub fn frame_info(&self, func_index: u32, offset: Option<usize>) -> FrameInfo {
+        FrameInfo::new(
+            func_index,
+            offset,
+            self.func_names.get(&func_index).cloned(),
+            offset.and_then(|offset| self.source_location(offset)),
+        )
+    }
+}
@@ -0,0 +1,71 @@
+use crate::context::create_isa;
+use crate::module_info::ModuleInfo;
+use cranelift_entity::EntityRef;
+use cranelift_wasm::{DefinedFuncIndex, FuncIndex};
+use failure::Error;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::process;
+use wasmtime_environ::{Compiler, Cranelift, Export, ModuleEnvironment, Tunables};
+use wasmtime_runtime::{self, InstanceHandle};
+
+/// Returns the code sizes of the functions defined in module `data`, by
+/// defined index. `wasmtime_jit` does not keep them, so the module is
+/// compiled once more the way the context compiles it.
+pub(crate) fn compiled_sizes(data: &[u8], debug_info: bool) -> Result<Vec<usize>, Error> {
+    let isa = create_isa();
+    let translation =
+        ModuleEnvironment::new(isa.frontend_config(), Tunables::default()).translate(data)?;
+    let (compilation, ..) = Cranelift::compile_module(
+        &translation.module,
+        translation.function_body_inputs,
+        &*isa,
+        debug_info,
+    )?;
+    Ok((0..compilation.len())
+        .map(|i| compilation.get(DefinedFuncIndex::new(i)).body.len())
+        .collect())
+}
+
+/// Returns address, size and index of the compiled functions of `instance`,
+/// sorted by address, given their `sizes` by defined index.
+fn code_ranges(
+    instance: &mut InstanceHandle,
+    sizes: &[usize],
+) -> Vec<(usize, usize, u32)> {
+    let imported_funcs = instance.module_ref().imported_funcs.len();
+
+    let mut ranges = Vec::new();
+    for (defined, &size) in sizes.iter().enumerate() {
+        let index = imported_funcs + defined;
+        let export = Export::Function(FuncIndex::new(index));
+        if let wasmtime_runtime::Export::Function { address, .. } =
+            instance.lookup_by_declaration(&export)
+        {
+            ranges.push((address as usize, size, index as u32));
+        }
+    }
+    ranges.sort_by_key(|&(address, _, _)| address);
+    ranges
+}
+
+/// Appends the compiled functions of `instance`, of `sizes` by defined
+/// index, to `/tmp/perf-<pid>.map`, which `perf report` uses to symbolize
+/// JIT code.
+pub(crate) fn write_perf_map(
+    instance: &mut InstanceHandle,
+    sizes: &[usize],
+    module_info: &ModuleInfo,
+) -> Result<(), Error> {
+    let path = format!("/tmp/perf-{}.map", process::id());
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    for (address, size, index) in code_ranges(instance, sizes) {
+        let index = module_info.original_index(index);
+        let name = match module_info.func_name(index) {
+            Some(name) => format!("wasm::{}", name),
+            None => format!("wasm::func[{}]", index),
+        };
+        writeln!(file, "{:x} {:x} {}", address, size, name)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use std::fs::{self, Metadata};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use tempfile::TempDir;
+
+/// Scratch filesystem in a private host temporary directory, which can be
+/// preopened into WASI instances with `WasiConfig::preopen_scratch`.
+///
+/// It is not kept in memory: wasi-common can only preopen host directories,
+/// so the files the embedder or the guest write go to the host disk, under
+/// the temporary directory of the host, and are subject to its permissions
+/// and quotas. The directory is removed with the last clone of `ScratchFs`,
+/// keep it alive while the instances use it. All paths are relative to its
+/// root, "/a" and "a" being the same file; ".." and paths through symbolic
+/// links, e.g. created by the guest, are rejected.
+#[derive(Clone)]
+pub struct ScratchFs(Rc<TempDir>);
+
+impl ScratchFs {
+    pub fn new() -> io::Result<ScratchFs> {
+        Ok(ScratchFs(Rc::new(TempDir::new()?)))
+    }
+
+    /// Creates filesystem populated with the entries of tar `archive`.
+    pub fn from_tar<R: Read>(archive: R) -> io::Result<ScratchFs> {
+        let fs = ScratchFs::new()?;
+        tar::Archive::new(archive).unpack(fs.root())?;
+        Ok(fs)
+    }
+
+    pub(crate) fn root(&self) -> &Path {
+        self.0.path()
+    }
+
+    /// Returns the host path of `path`, which must not lead out of the root
+    /// by ".." or by a symbolic link.
+    fn resolve<P: AsRef<Path>>(&self, path: P) -> io::Result<PathBuf> {
+        let escapes = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("path escapes scratch filesystem: {}", path.as_ref().display()),
+            )
+        };
+        let mut resolved = self.root().to_owned();
+        // Components after a missing one do not exist either, e.g. to be
+        // created, and need not be checked.
+        let mut missing = false;
+        for component in path.as_ref().components() {
+            match component {
+                Component::Normal(name) => resolved.push(name),
+                Component::RootDir | Component::CurDir => continue,
+                _ => return Err(escapes()),
+            }
+            if missing {
+                continue;
+            }
+            match fs::symlink_metadata(&resolved) {
+                Ok(metadata) if metadata.file_type().is_symlink() => return Err(escapes()),
+                Ok(_) => (),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => missing = true,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// Creates directory `path` and its missing parents.
+    pub fn create_dir<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::create_dir_all(self.resolve(path)?)
+    }
+
+    /// Writes file `path`, creating its missing parent directories.
+    pub fn write_file<P, C>(&self, path: P, contents: C) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        C: AsRef<[u8]>,
+    {
+        let path = self.resolve(path)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, contents)
+    }
+
+    pub fn read_file<P: AsRef<Path>>(&self, path: P) -> io::Result<Vec<u8>> {
+        fs::read(self.resolve(path)?)
+    }
+
+    /// Returns names of the directory `path` entries, sorted.
+    pub fn read_dir<P: AsRef<Path>>(&self, path: P) -> io::Result<Vec<String>> {
+        let mut names = fs::read_dir(self.resolve(path)?)?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+            .collect::<io::Result<Vec<_>>>()?;
+        names.sort();
+        Ok(names)
+    }
+
+    pub fn exists<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.resolve(path).map_or(false, |path| path.exists())
+    }
+
+    pub fn metadata<P: AsRef<Path>>(&self, path: P) -> io::Result<Metadata> {
+        fs::metadata(self.resolve(path)?)
+    }
+
+    /// Makes file or directory `path` read-only for the guest.
+    pub fn set_readonly<P: AsRef<Path>>(&self, path: P, readonly: bool) -> io::Result<()> {
+        let path = self.resolve(path)?;
+        let mut permissions = fs::metadata(&path)?.permissions();
+        permissions.set_readonly(readonly);
+        fs::set_permissions(path, permissions)
+    }
+
+    /// Removes file or directory `path` with its contents.
+    pub fn remove<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = self.resolve(path)?;
+        if fs::metadata(&path)?.is_dir() {
+            fs::remove_dir_all(path)
+        } else {
+            fs::remove_file(path)
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use crate::config::Config;
+use crate::context::ContextToken;
+use crate::imports::ImportSet;
+use crate::instance::InstanceToken;
+use crate::instantiate::instantiate_in_context;
+use crate::trap::Trap;
+use failure::Error;
+use std::any::Any;
+use std::cell::{Cell, Ref, RefCell, RefMut};
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+thread_local! {
+    static LIVE_INSTANCES: Cell<usize> = Cell::new(0);
+    static LIVE_CONTEXTS: Cell<usize> = Cell::new(0);
+    static LIVE_CODE_BYTES: Cell<usize> = Cell::new(0);
+
+    // Data of the stores whose instances the host entered, innermost last.
+    static STORE_DATA: RefCell<Vec<Option<Weak<dyn Any>>>> = RefCell::new(Vec::new());
+}
+
+/// Numbers of the objects alive in the current thread, see `live_counts`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LiveCounts {
+    /// Instances referenced by an `InstanceToken`.
+    pub instances: usize,
+    pub contexts: usize,
+    /// Size of the host function trampolines and of the compiled module
+    /// functions. Learning the size of the module functions takes a second
+    /// compilation of every instantiated module.
+    pub code_bytes: usize,
+}
+
+/// Returns numbers of the instances, contexts and code bytes alive in the
+/// current thread, e.g. to check that a long-running process does not leak
+/// them.
+pub fn live_counts() -> LiveCounts {
+    LiveCounts {
+        instances: LIVE_INSTANCES.with(Cell::get),
+        contexts: LIVE_CONTEXTS.with(Cell::get),
+        code_bytes: LIVE_CODE_BYTES.with(Cell::get),
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum LiveKind {
+    Instance,
+    Context,
+    CodeBytes,
+}
+
+/// Adds `amount` to the live count of `kind` until dropped.
+pub(crate) struct LiveGuard {
+    kind: LiveKind,
+    amount: usize,
+}
+
+impl LiveGuard {
+    pub fn new(kind: LiveKind, amount: usize) -> LiveGuard {
+        update(kind, |count| count + amount);
+        LiveGuard { kind, amount }
+    }
+}
+
+impl Drop for LiveGuard {
+    fn drop(&mut self) {
+        let amount = self.amount;
+        update(self.kind, |count| count - amount);
+    }
+}
+
+fn update<F: FnOnce(usize) -> usize>(kind: LiveKind, f: F) {
+    let counter = match kind {
+        LiveKind::Instance => &LIVE_INSTANCES,
+        LiveKind::Context => &LIVE_CONTEXTS,
+        LiveKind::CodeBytes => &LIVE_CODE_BYTES,
+    };
+    counter.with(|count| count.set(f(count.get())));
+}
+
+/// Registers the data of the store of the entered instance until dropped,
+/// see `InstanceToken::enter_store`.
+pub(crate) struct StoreGuard;
+
+pub(crate) fn enter_store(data: Option<Weak<dyn Any>>) -> StoreGuard {
+    STORE_DATA.with(|stack| stack.borrow_mut().push(data));
+    StoreGuard
+}
+
+impl Drop for StoreGuard {
+    fn drop(&mut self) {
+        STORE_DATA.with(|stack| stack.borrow_mut().pop());
+    }
+}
+
+/// Calls `f` with the data of the store, whose instance the host called
+/// into most recently, e.g. from a host function. Fails if there is no
+/// such store, its data is not `T` or is already borrowed, e.g. by a host
+/// function calling back into wasm.
+pub fn with_store_data<T: 'static, R, F: FnOnce(&mut T) -> R>(f: F) -> Result<R, Trap> {
+    let data = STORE_DATA
+        .with(|stack| stack.borrow().iter().rev().find_map(|data| data.clone()))
+        .and_then(|data| data.upgrade())
+        .ok_or_else(|| Trap::new("no store data available"))?;
+    let data = data
+        .downcast_ref::<RefCell<T>>()
+        .ok_or_else(|| Trap::new("store data has another type"))?;
+    let mut data = data
+        .try_borrow_mut()
+        .map_err(|_| Trap::new("store data is already borrowed"))?;
+    Ok(f(&mut *data))
+}
+
+/// Owner of the instances created in its context and of the data `T`
+/// available to their host functions, e.g.
+///
+/// ```ignore
+/// let mut store = Store::with_data(Vec::new());
+/// let log = store.add(
+///     HostModuleBuilder::new()
+///         .wrap_with_data("log", |log: &mut Vec<i32>, n: i32| log.push(n))
+///         .build(),
+/// );
+/// imports.insert(String::from("env"), ImportSet::InstanceExports(log));
+/// let instance = store.instantiate(&data, imports)?;
+/// ...
+/// store.clear();
+/// assert_eq!(live_counts().instances, 0);
+/// ```
+///
+/// The data is available while the host calls an export of an instance of
+/// the store, see `with_store_data`. The instances, their code and the
+/// context are released by `clear` or when the store is dropped, once the
+/// `InstanceToken`s and exports handed out are dropped as well. Every token
+/// keeps the contexts of its instance and imports alive, so do the tokens
+/// captured by host functions, e.g. to call back into the store instances,
+/// which makes the context keep itself alive.
+pub struct Store<T: 'static = ()> {
+    context: ContextToken,
+    instances: Vec<InstanceToken>,
+    data: Rc<RefCell<T>>,
+}
+
+impl Store<()> {
+    pub fn new() -> Store<()> {
+        Store::with_config(Config::default())
+    }
+
+    pub fn with_config(config: Config) -> Store<()> {
+        Store::from_context(ContextToken::with_config(config), ())
+    }
+}
+
+impl<T: 'static> Store<T> {
+    pub fn with_data(data: T) -> Store<T> {
+        Store::with_data_and_config(data, Config::default())
+    }
+
+    /// Creates the store with `data` and its context with `config`.
+    pub fn with_data_and_config(data: T, config: Config) -> Store<T> {
+        Store::from_context(ContextToken::with_config(config), data)
+    }
+
+    pub fn from_context(context: ContextToken, data: T) -> Store<T> {
+        Store {
+            context,
+            instances: Vec::new(),
+            data: Rc::new(RefCell::new(data)),
+        }
+    }
+
+    pub fn context(&self) -> &ContextToken {
+        &self.context
+    }
+
+    pub fn data(&self) -> Ref<T> {
+        self.data.borrow()
+    }
+
+    pub fn data_mut(&self) -> RefMut<T> {
+        self.data.borrow_mut()
+    }
+
+    fn adopt(&mut self, instance: InstanceToken) -> InstanceToken {
+        let data: Rc<dyn Any> = self.data.clone();
+        let instance = instance.with_store_data(Rc::downgrade(&data));
+        self.instances.push(instance.clone());
+        instance
+    }
+
+    /// Instantiates module `data` in the store context.
+    pub fn instantiate(
+        &mut self,
+        data: &[u8],
+        imports: HashMap<String, ImportSet>,
+    ) -> Result<InstanceToken, Error> {
+        let instance = instantiate_in_context(data, imports, self.context.clone())?;
+        Ok(self.adopt(instance))
+    }
+
+    /// Makes the store own `instance` created elsewhere, e.g. a host module.
+    /// Only the returned token enters the store when calling its exports.
+    pub fn add(&mut self, instance: InstanceToken) -> InstanceToken {
+        self.adopt(instance)
+    }
+
+    pub fn instances(&self) -> &[InstanceToken] {
+        &self.instances
+    }
+
+    /// Drops all instances and replaces the context, which keeps the
+    /// instances named for the imports, with a new one of the same config.
+    /// The old context is released once no `InstanceToken` of it is left,
+    /// see `live_counts`.
+    pub fn clear(&mut self) {
+        self.instances.clear();
+        self.context = self.context.renew();
+    }
+}
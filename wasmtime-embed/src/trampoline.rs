@@ -0,0 +1,150 @@
+use cranelift_codegen::ir::{InstBuilder, MemFlags, StackSlotData, StackSlotKind, TrapCode};
+use cranelift_codegen::{binemit, ir, isa, Context};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use std::cmp;
+use wasmtime_jit::CodeMemory;
+use wasmtime_runtime::{VMContext, VMFunctionBody};
+
+/// Size of a slot in the values vector passed to the stub.
+pub(crate) const VALUE_SIZE: usize = 8;
+
+/// Signature of the stub called by trampolines:
+/// `unsafe extern "sysv64" fn(vmctx: *mut VMContext, values_vec: *mut u8) -> u32`.
+/// The stub reads the arguments from and writes the results to `values_vec`,
+/// and returns non-zero to raise a trap.
+pub(crate) type StubFn = unsafe extern "sysv64" fn(*mut VMContext, *mut u8) -> u32;
+
+struct RelocSink;
+
+impl binemit::RelocSink for RelocSink {
+    fn reloc_ebb(
+        &mut self,
+        _offset: binemit::CodeOffset,
+        _reloc: binemit::Reloc,
+        _ebb_offset: binemit::CodeOffset,
+    ) {
+        panic!("trampoline compilation should not produce ebb relocs");
+    }
+    fn reloc_external(
+        &mut self,
+        _offset: binemit::CodeOffset,
+        _reloc: binemit::Reloc,
+        _name: &ir::ExternalName,
+        _addend: binemit::Addend,
+    ) {
+        panic!("trampoline compilation should not produce external symbol relocs");
+    }
+    fn reloc_constant(
+        &mut self,
+        _code_offset: binemit::CodeOffset,
+        _reloc: binemit::Reloc,
+        _constant_offset: ir::ConstantOffset,
+    ) {
+        panic!("trampoline compilation should not produce constant relocs");
+    }
+    fn reloc_jt(
+        &mut self,
+        _offset: binemit::CodeOffset,
+        _reloc: binemit::Reloc,
+        _jt: ir::JumpTable,
+    ) {
+        panic!("trampoline compilation should not produce jump table relocs");
+    }
+}
+
+/// Compiles a function with `signature` (as called from wasm) that spills its
+/// arguments into a values vector, calls `stub` and loads the results back.
+/// This is the reverse of the trampoline `wasmtime_jit` uses for `invoke`.
+/// Returns the function body and its code size.
+pub(crate) fn make_trampoline(
+    isa: &dyn isa::TargetIsa,
+    code_memory: &mut CodeMemory,
+    signature: &ir::Signature,
+    stub: StubFn,
+) -> (*const VMFunctionBody, usize) {
+    let pointer_type = isa.pointer_type();
+
+    let mut stub_sig = ir::Signature::new(isa::CallConv::SystemV);
+    // `vmctx` parameter.
+    stub_sig.params.push(ir::AbiParam::special(
+        pointer_type,
+        ir::ArgumentPurpose::VMContext,
+    ));
+    // `values_vec` parameter.
+    stub_sig.params.push(ir::AbiParam::new(pointer_type));
+    // Trap flag.
+    stub_sig.returns.push(ir::AbiParam::new(ir::types::I32));
+
+    let values_vec_len = cmp::max(
+        cmp::max(signature.params.len() - 1, signature.returns.len()),
+        1,
+    ) * VALUE_SIZE;
+
+    let mut context = Context::new();
+    context.func =
+        ir::Function::with_name_signature(ir::ExternalName::user(0, 0), signature.clone());
+    let ss = context.func.create_stack_slot(StackSlotData::new(
+        StackSlotKind::ExplicitSlot,
+        values_vec_len as u32,
+    ));
+
+    let mut fn_builder_ctx = FunctionBuilderContext::new();
+    {
+        let mut builder = FunctionBuilder::new(&mut context.func, &mut fn_builder_ctx);
+        let block0 = builder.create_ebb();
+
+        builder.append_ebb_params_for_function_params(block0);
+        builder.switch_to_block(block0);
+        builder.seal_block(block0);
+
+        let values_vec_ptr_val = builder.ins().stack_addr(pointer_type, ss, 0);
+        let mflags = MemFlags::trusted();
+        for i in 1..signature.params.len() {
+            let val = builder.func.dfg.ebb_params(block0)[i];
+            builder.ins().store(
+                mflags,
+                val,
+                values_vec_ptr_val,
+                ((i - 1) * VALUE_SIZE) as i32,
+            );
+        }
+
+        let vmctx_ptr_val = builder.func.dfg.ebb_params(block0)[0];
+        let callee_args = vec![vmctx_ptr_val, values_vec_ptr_val];
+
+        let new_sig = builder.import_signature(stub_sig);
+        let callee_value = builder.ins().iconst(pointer_type, stub as usize as i64);
+        let call = builder
+            .ins()
+            .call_indirect(new_sig, callee_value, &callee_args);
+
+        let call_result = builder.inst_results(call)[0];
+        builder.ins().trapnz(call_result, TrapCode::User(0));
+
+        let mut results = Vec::new();
+        for (i, r) in signature.returns.iter().enumerate() {
+            let load = builder.ins().load(
+                r.value_type,
+                mflags,
+                values_vec_ptr_val,
+                (i * VALUE_SIZE) as i32,
+            );
+            results.push(load);
+        }
+        builder.ins().return_(&results);
+        builder.finalize()
+    }
+
+    let mut code_buf: Vec<u8> = Vec::new();
+    let mut reloc_sink = RelocSink;
+    let mut trap_sink = binemit::NullTrapSink {};
+    context
+        .compile_and_emit(isa, &mut code_buf, &mut reloc_sink, &mut trap_sink)
+        .expect("compile_and_emit");
+
+    let body = code_memory
+        .allocate_copy_of_byte_slice(&code_buf)
+        .expect("allocate_copy_of_byte_slice")
+        .as_ptr();
+    (body, code_buf.len())
+}
@@ -0,0 +1,308 @@
+use crate::module_info::ModuleInfo;
+use cranelift_codegen::ir;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Reason of a trap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapCode {
+    /// Trap raised by a host function.
+    User,
+    /// The instance ran out of fuel, see `Config::consume_fuel`.
+    OutOfFuel,
+    /// The execution was interrupted, see `InterruptHandle`.
+    Interrupt,
+    /// The `unreachable` instruction was executed.
+    Unreachable,
+    /// Integer arithmetic overflowed, e.g. `i32.div_s` of `i32::MIN` by -1.
+    IntegerOverflow,
+    /// Integer division by zero.
+    IntegerDivisionByZero,
+    /// Failed float to integer conversion.
+    BadConversionToInteger,
+    /// Out-of-bounds linear memory access.
+    MemoryOutOfBounds,
+    /// Out-of-bounds table access.
+    TableOutOfBounds,
+    /// `call_indirect` of a null table element.
+    IndirectCallToNull,
+    /// `call_indirect` signature mismatch.
+    BadSignature,
+    /// The wasm stack was exhausted.
+    StackOverflow,
+    /// Trap not recognized by the embedder.
+    Unknown,
+}
+
+impl TrapCode {
+    fn from_ir(code: ir::TrapCode) -> TrapCode {
+        match code {
+            ir::TrapCode::StackOverflow => TrapCode::StackOverflow,
+            ir::TrapCode::HeapOutOfBounds | ir::TrapCode::OutOfBounds => {
+                TrapCode::MemoryOutOfBounds
+            }
+            ir::TrapCode::TableOutOfBounds => TrapCode::TableOutOfBounds,
+            ir::TrapCode::IndirectCallToNull => TrapCode::IndirectCallToNull,
+            ir::TrapCode::BadSignature => TrapCode::BadSignature,
+            ir::TrapCode::IntegerOverflow => TrapCode::IntegerOverflow,
+            ir::TrapCode::IntegerDivisionByZero => TrapCode::IntegerDivisionByZero,
+            ir::TrapCode::BadConversionToInteger => TrapCode::BadConversionToInteger,
+            ir::TrapCode::UnreachableCodeReached => TrapCode::Unreachable,
+            ir::TrapCode::Interrupt => TrapCode::Interrupt,
+            ir::TrapCode::User(_) => TrapCode::User,
+        }
+    }
+}
+
+impl fmt::Display for TrapCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            TrapCode::User => "host trap",
+            TrapCode::OutOfFuel => "all fuel consumed",
+            TrapCode::Interrupt => "interrupted",
+            TrapCode::Unreachable => "unreachable executed",
+            TrapCode::IntegerOverflow => "integer overflow",
+            TrapCode::IntegerDivisionByZero => "integer divide by zero",
+            TrapCode::BadConversionToInteger => "invalid conversion to integer",
+            TrapCode::MemoryOutOfBounds => "out of bounds memory access",
+            TrapCode::TableOutOfBounds => "undefined element",
+            TrapCode::IndirectCallToNull => "uninitialized element",
+            TrapCode::BadSignature => "indirect call type mismatch",
+            TrapCode::StackOverflow => "call stack exhausted",
+            TrapCode::Unknown => "unknown trap",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+/// Location of a wasm frame.
+#[derive(Debug, Clone)]
+pub struct FrameInfo {
+    func_index: u32,
+    module_offset: Option<usize>,
+    func_name: Option<String>,
+    source_location: Option<(Arc<str>, u32)>,
+}
+
+impl FrameInfo {
+    pub(crate) fn new(
+        func_index: u32,
+        module_offset: Option<usize>,
+        func_name: Option<String>,
+        source_location: Option<(Arc<str>, u32)>,
+    ) -> FrameInfo {
+        FrameInfo {
+            func_index,
+            module_offset,
+            func_name,
+            source_location,
+        }
+    }
+
+    /// Index of the function in the module function index space.
+    pub fn func_index(&self) -> u32 {
+        self.func_index
+    }
+
+    /// Offset of the executed instruction in the module binary. Known only
+    /// for the faulting frame.
+    pub fn module_offset(&self) -> Option<usize> {
+        self.module_offset
+    }
+
+    /// Function name from the module "name" section.
+    pub fn func_name(&self) -> Option<&str> {
+        self.func_name.as_ref().map(String::as_str)
+    }
+
+    /// Source file and line from the module DWARF, available for the
+    /// faulting frame when the context generates debug info.
+    pub fn source_location(&self) -> Option<(&str, u32)> {
+        self.source_location
+            .as_ref()
+            .map(|(file, line)| (&**file, *line))
+    }
+}
+
+impl fmt::Display for FrameInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.func_name {
+            Some(ref name) => write!(f, "{}", name)?,
+            None => write!(f, "func[{}]", self.func_index)?,
+        }
+        if let Some((ref file, line)) = self.source_location {
+            write!(f, " at {}:{}", file, line)?;
+        } else if let Some(offset) = self.module_offset {
+            write!(f, " @ 0x{:x}", offset)?;
+        }
+        Ok(())
+    }
+}
+
+/// Trap raised by wasm code or by a host function called from wasm.
+///
+/// The embedder cannot unwind wasm frames, so `frames` holds the faulting
+/// frame (when the trap originates in wasm code) followed by the functions
+/// through which the host entered wasm, innermost first.
+#[derive(Fail, Debug, Clone)]
+pub struct Trap {
+    code: TrapCode,
+    message: String,
+    frames: Vec<FrameInfo>,
+}
+
+impl Trap {
+    pub fn new<S: Into<String>>(message: S) -> Trap {
+        Trap::with_code(TrapCode::User, message)
+    }
+
+    pub(crate) fn with_code<S: Into<String>>(code: TrapCode, message: S) -> Trap {
+        Trap {
+            code,
+            message: message.into(),
+            frames: Vec::new(),
+        }
+    }
+
+    /// Creates trap from the `wasmtime_runtime` trap message, which looks
+    /// like "wasm trap: heap_oob, source location: @0042".
+    fn from_wasm_trap(message: &str, module_info: Option<&ModuleInfo>) -> Trap {
+        let mut parts = message
+            .trim_start_matches("wasm trap: ")
+            .splitn(2, ", source location: @");
+        let code = parts
+            .next()
+            .and_then(|code| ir::TrapCode::from_str(code).ok())
+            .map_or(TrapCode::Unknown, TrapCode::from_ir);
+        let offset = parts
+            .next()
+            .and_then(|offset| usize::from_str_radix(offset, 16).ok());
+
+        let mut frames = Vec::new();
+        if let (Some(offset), Some(info)) = (offset, module_info) {
+            if let Some(func_index) = info.func_index_at(offset) {
+                frames.push(info.frame_info(func_index, Some(offset)));
+            }
+        }
+
+        let message = match code {
+            TrapCode::Unknown => message.to_owned(),
+            _ => code.to_string(),
+        };
+        Trap {
+            code,
+            message,
+            frames,
+        }
+    }
+
+    pub fn code(&self) -> TrapCode {
+        self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Known wasm frames, innermost first.
+    pub fn frames(&self) -> &[FrameInfo] {
+        &self.frames
+    }
+
+    /// Index of the faulting wasm function, if the trap originates in wasm.
+    pub fn func_index(&self) -> Option<u32> {
+        self.faulting_frame().map(FrameInfo::func_index)
+    }
+
+    /// Offset of the faulting instruction in the module binary, if the trap
+    /// originates in wasm.
+    pub fn module_offset(&self) -> Option<usize> {
+        self.faulting_frame().and_then(FrameInfo::module_offset)
+    }
+
+    fn faulting_frame(&self) -> Option<&FrameInfo> {
+        self.frames.first().filter(|f| f.module_offset.is_some())
+    }
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "wasm trap: {}", self.message)?;
+        for frame in &self.frames {
+            write!(f, "\n  at {}", frame)?;
+        }
+        Ok(())
+    }
+}
+
+thread_local! {
+    // Host functions cannot return a `Trap` through wasm frames, so it is
+    // parked here until the trap unwinds back to the invoking host code.
+    static RECORDED_TRAP: RefCell<Option<Trap>> = RefCell::new(None);
+
+    // Functions through which the host entered wasm code, outermost first.
+    static ENTRY_FRAMES: RefCell<Vec<(Option<Rc<ModuleInfo>>, Option<FrameInfo>)>> =
+        RefCell::new(Vec::new());
+}
+
+pub(crate) fn record_trap(trap: Trap) {
+    RECORDED_TRAP.with(|recorded| *recorded.borrow_mut() = Some(trap));
+}
+
+/// Converts the trap `message` of a call made within `enter_wasm` into
+/// `Trap`, preferring the trap recorded by a host function.
+pub(crate) fn take_trap(message: &str) -> Trap {
+    let recorded = RECORDED_TRAP.with(|recorded| recorded.borrow_mut().take());
+    ENTRY_FRAMES.with(|entries| {
+        let entries = entries.borrow();
+        let mut trap = match recorded {
+            // A trap propagated from a nested call already has the frames.
+            Some(trap) if !trap.frames.is_empty() => return trap,
+            Some(trap) => trap,
+            None => {
+                let module_info = entries.last().and_then(|(info, _)| info.clone());
+                Trap::from_wasm_trap(message, module_info.as_ref().map(|info| &**info))
+            }
+        };
+        trap.frames
+            .extend(entries.iter().rev().filter_map(|(_, frame)| frame.clone()));
+        trap
+    })
+}
+
+/// Registers the host entry into wasm function `frame` until dropped.
+pub(crate) struct EntryGuard;
+
+/// Registers the host entry into wasm, failing if there are already
+/// `max_depth` entries, e.g. of host functions calling back into wasm.
+pub(crate) fn enter_wasm(
+    module_info: Option<Rc<ModuleInfo>>,
+    frame: Option<FrameInfo>,
+    max_depth: Option<usize>,
+) -> Result<EntryGuard, Trap> {
+    ENTRY_FRAMES.with(|entries| {
+        let mut entries = entries.borrow_mut();
+        if let Some(max_depth) = max_depth {
+            if entries.len() >= max_depth {
+                return Err(Trap::with_code(
+                    TrapCode::StackOverflow,
+                    format!("call depth exceeds the limit of {}", max_depth),
+                ));
+            }
+        }
+        entries.push((module_info, frame));
+        // A trap recorded by a host function but never taken must not be
+        // reported for an unrelated trap of this call.
+        RECORDED_TRAP.with(|recorded| recorded.borrow_mut().take());
+        Ok(EntryGuard)
+    })
+}
+
+impl Drop for EntryGuard {
+    fn drop(&mut self) {
+        ENTRY_FRAMES.with(|entries| entries.borrow_mut().pop());
+    }
+}
@@ -0,0 +1,124 @@
+use crate::func::{WasmRet, WasmTy};
+use crate::instance::{InstanceCallableExport, InstanceToken};
+use crate::trap::{take_trap, Trap};
+use crate::types::{FuncType, ValType};
+use failure::Error;
+use std::marker::PhantomData;
+use std::mem;
+use wasmtime_runtime::{wasmtime_call_trampoline, VMContext, VMFunctionBody};
+
+/// Parameters of a `TypedFunc`: tuples of `WasmTy`.
+pub trait WasmParams: 'static {
+    fn val_types() -> Vec<ValType>;
+
+    #[doc(hidden)]
+    unsafe fn call<R: WasmRet>(self, vmctx: *mut VMContext, body: *const VMFunctionBody) -> R;
+}
+
+macro_rules! wasm_params {
+    ($($arg_t:ident $arg:ident),*) => {
+        impl<$($arg_t: WasmTy,)*> WasmParams for ($($arg_t,)*) {
+            fn val_types() -> Vec<ValType> {
+                vec![$($arg_t::val_type()),*]
+            }
+
+            unsafe fn call<R: WasmRet>(
+                self,
+                vmctx: *mut VMContext,
+                body: *const VMFunctionBody,
+            ) -> R {
+                let f: unsafe extern "sysv64" fn(*mut VMContext $(, $arg_t)*) -> R =
+                    mem::transmute(body);
+                let ($($arg,)*) = self;
+                f(vmctx $(, $arg)*)
+            }
+        }
+    };
+}
+
+wasm_params!();
+wasm_params!(A1 a1);
+wasm_params!(A1 a1, A2 a2);
+wasm_params!(A1 a1, A2 a2, A3 a3);
+wasm_params!(A1 a1, A2 a2, A3 a3, A4 a4);
+wasm_params!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5);
+wasm_params!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6);
+wasm_params!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7);
+wasm_params!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7, A8 a8);
+wasm_params!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7, A8 a8, A9 a9);
+
+/// Statically typed exported function. Its calls, like those of the
+/// `#[wasm_export]` proxies, catch traps (including interrupts and running
+/// out of fuel) and report them as errors.
+pub struct TypedFunc<P, R> {
+    export: InstanceCallableExport,
+    name: String,
+    _marker: PhantomData<fn(P) -> R>,
+}
+
+struct CallFrame<F, R> {
+    call: Option<F>,
+    result: Option<R>,
+}
+
+// Called by `wasmtime_call_trampoline`, which catches traps.
+unsafe extern "C" fn call_shim<F: FnOnce() -> R, R>(_vmctx: *mut VMContext, frame: *mut u8) {
+    let frame = &mut *(frame as *mut CallFrame<F, R>);
+    let call = frame.call.take().expect("call");
+    frame.result = Some(call());
+}
+
+impl<P: WasmParams, R: WasmRet> TypedFunc<P, R> {
+    pub fn ty(&self) -> FuncType {
+        FuncType::new(P::val_types(), R::val_types())
+    }
+
+    pub fn call(&self, params: P) -> Result<R, Error> {
+        let (vmctx, body) = self.export.vmctx_and_body();
+        let call = move || unsafe { params.call::<R>(vmctx, body) };
+        Ok(unsafe { self.export.instance.call_export(&self.name, vmctx, call) }?)
+    }
+}
+
+impl InstanceToken {
+    /// Runs `call` of exported function `name` with `vmctx` as
+    /// `TypedFunc::call` does: within the call depth limit, with the store
+    /// data current and catching traps. Used by the `#[wasm_export]` proxies,
+    /// `vmctx` must be the context of the export.
+    #[doc(hidden)]
+    pub unsafe fn call_export<F: FnOnce() -> R, R>(
+        &self,
+        name: &str,
+        vmctx: *mut VMContext,
+        call: F,
+    ) -> Result<R, Trap> {
+        let _entry = self.enter_export(name)?;
+        let mut frame = CallFrame {
+            call: Some(call),
+            result: None,
+        };
+        let outcome = wasmtime_call_trampoline(
+            vmctx,
+            call_shim::<F, R> as *const VMFunctionBody,
+            &mut frame as *mut CallFrame<F, R> as *mut u8,
+        );
+        match outcome {
+            Ok(()) => Ok(frame.result.take().expect("result")),
+            Err(message) => Err(take_trap(&message)),
+        }
+    }
+
+    /// Looks up exported function and checks it has type `P -> R`, e.g.
+    /// `instance.get_typed_func::<(u32, u32), u32>("gcd")`.
+    pub fn get_typed_func<P: WasmParams, R: WasmRet>(
+        &self,
+        name: &str,
+    ) -> Result<TypedFunc<P, R>, Error> {
+        let ty = FuncType::new(P::val_types(), R::val_types());
+        Ok(TypedFunc {
+            export: self.get_callable_export(name, &ty)?,
+            name: name.to_owned(),
+            _marker: PhantomData,
+        })
+    }
+}
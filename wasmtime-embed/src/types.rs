@@ -0,0 +1,175 @@
+use cranelift_codegen::{ir, isa};
+use cranelift_wasm::{Memory, Table, TableElementType};
+use std::fmt;
+
+/// Type of a wasm value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValType {
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+impl ValType {
+    pub(crate) fn ir_type(self) -> ir::Type {
+        match self {
+            ValType::I32 => ir::types::I32,
+            ValType::I64 => ir::types::I64,
+            ValType::F32 => ir::types::F32,
+            ValType::F64 => ir::types::F64,
+        }
+    }
+
+    pub(crate) fn from_ir_type(ty: ir::Type) -> Option<ValType> {
+        match ty {
+            ir::types::I32 => Some(ValType::I32),
+            ir::types::I64 => Some(ValType::I64),
+            ir::types::F32 => Some(ValType::F32),
+            ir::types::F64 => Some(ValType::F64),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ValType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValType::I32 => write!(f, "i32"),
+            ValType::I64 => write!(f, "i64"),
+            ValType::F32 => write!(f, "f32"),
+            ValType::F64 => write!(f, "f64"),
+        }
+    }
+}
+
+/// Type of a wasm function: its parameters and results.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuncType {
+    params: Vec<ValType>,
+    results: Vec<ValType>,
+}
+
+impl FuncType {
+    pub fn new(params: Vec<ValType>, results: Vec<ValType>) -> FuncType {
+        FuncType { params, results }
+    }
+
+    pub fn params(&self) -> &[ValType] {
+        &self.params
+    }
+
+    pub fn results(&self) -> &[ValType] {
+        &self.results
+    }
+
+    /// Signature of the native function body, including the leading
+    /// `VMContext` parameter.
+    pub(crate) fn signature(&self) -> ir::Signature {
+        let mut params = vec![ir::AbiParam::special(
+            ir::types::I64,
+            ir::ArgumentPurpose::VMContext,
+        )];
+        params.extend(self.params.iter().map(|ty| ir::AbiParam::new(ty.ir_type())));
+        ir::Signature {
+            params,
+            returns: self
+                .results
+                .iter()
+                .map(|ty| ir::AbiParam::new(ty.ir_type()))
+                .collect(),
+            call_conv: isa::CallConv::SystemV,
+        }
+    }
+
+    pub(crate) fn from_signature(signature: &ir::Signature) -> Option<FuncType> {
+        let params = signature
+            .params
+            .iter()
+            .filter(|p| p.purpose == ir::ArgumentPurpose::Normal)
+            .map(|p| ValType::from_ir_type(p.value_type))
+            .collect::<Option<Vec<_>>>()?;
+        let results = signature
+            .returns
+            .iter()
+            .map(|p| ValType::from_ir_type(p.value_type))
+            .collect::<Option<Vec<_>>>()?;
+        Some(FuncType { params, results })
+    }
+}
+
+impl fmt::Display for FuncType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn list(f: &mut fmt::Formatter, types: &[ValType]) -> fmt::Result {
+            write!(f, "(")?;
+            for (i, ty) in types.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", ty)?;
+            }
+            write!(f, ")")
+        }
+        list(f, &self.params)?;
+        write!(f, " -> ")?;
+        list(f, &self.results)
+    }
+}
+
+/// Type of a linear memory, in wasm pages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryType {
+    minimum: u32,
+    maximum: Option<u32>,
+}
+
+impl MemoryType {
+    pub fn new(minimum: u32, maximum: Option<u32>) -> MemoryType {
+        MemoryType { minimum, maximum }
+    }
+
+    pub fn minimum(&self) -> u32 {
+        self.minimum
+    }
+
+    pub fn maximum(&self) -> Option<u32> {
+        self.maximum
+    }
+
+    pub(crate) fn memory(&self) -> Memory {
+        Memory {
+            minimum: self.minimum,
+            maximum: self.maximum,
+            shared: false,
+        }
+    }
+}
+
+/// Type of a table of function references.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableType {
+    minimum: u32,
+    maximum: Option<u32>,
+}
+
+impl TableType {
+    pub fn new(minimum: u32, maximum: Option<u32>) -> TableType {
+        TableType { minimum, maximum }
+    }
+
+    pub fn minimum(&self) -> u32 {
+        self.minimum
+    }
+
+    pub fn maximum(&self) -> Option<u32> {
+        self.maximum
+    }
+
+    pub(crate) fn table(&self) -> Table {
+        Table {
+            ty: TableElementType::Func,
+            minimum: self.minimum,
+            maximum: self.maximum,
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use crate::config::Config;
+use wasmparser::{
+    ImportSectionEntryType, OperatorValidatorConfig, ParserState, SectionCode, ValidatingParser,
+    ValidatingParserConfig, WasmDecoder,
+};
+
+#[derive(Fail, Debug)]
+#[fail(display = "Invalid module at offset {}: {}", offset, message)]
+pub struct ValidationError {
+    pub message: String,
+    /// Offset of the error in the module binary.
+    pub offset: usize,
+    /// Name of the section containing the error, e.g. "code" or the name
+    /// of a custom section.
+    pub section: Option<String>,
+    /// Index of the function containing the error, if within a body.
+    pub func_index: Option<u32>,
+}
+
+/// Module using a wasm proposal enabled in `Config`, which passes validation
+/// but cannot be compiled.
+#[derive(Fail, Debug)]
+#[fail(display = "Module uses an unsupported wasm proposal: {}", _0)]
+pub struct UnsupportedFeature(pub ValidationError);
+
+fn section_name(code: &SectionCode) -> String {
+    match code {
+        SectionCode::Custom { name, .. } => name.to_string(),
+        SectionCode::Type => "type".to_owned(),
+        SectionCode::Import => "import".to_owned(),
+        SectionCode::Function => "function".to_owned(),
+        SectionCode::Table => "table".to_owned(),
+        SectionCode::Memory => "memory".to_owned(),
+        SectionCode::Global => "global".to_owned(),
+        SectionCode::Export => "export".to_owned(),
+        SectionCode::Start => "start".to_owned(),
+        SectionCode::Element => "element".to_owned(),
+        SectionCode::Code => "code".to_owned(),
+        SectionCode::Data => "data".to_owned(),
+        SectionCode::DataCount => "datacount".to_owned(),
+    }
+}
+
+fn parser_config(config: &Config) -> ValidatingParserConfig {
+    ValidatingParserConfig {
+        operator_config: OperatorValidatorConfig {
+            enable_threads: config.threads_enabled(),
+            enable_reference_types: config.reference_types_enabled(),
+            enable_simd: config.simd_enabled(),
+            enable_bulk_memory: config.bulk_memory_enabled(),
+        },
+        mutable_global_imports: true,
+    }
+}
+
+/// Validates binary module `data` with the wasm features enabled in
+/// `config`, without compiling it.
+pub fn validate(data: &[u8], config: &Config) -> Result<(), ValidationError> {
+    let mut parser = ValidatingParser::new(data, Some(parser_config(config)));
+    let mut section = None;
+    let mut imported_funcs = 0;
+    let mut defined_funcs = 0;
+    let mut func_index = None;
+
+    loop {
+        match *parser.read() {
+            ParserState::EndWasm => return Ok(()),
+            ParserState::Error(ref e) => {
+                return Err(ValidationError {
+                    message: e.message.to_owned(),
+                    offset: e.offset,
+                    section,
+                    func_index,
+                });
+            }
+            ParserState::BeginSection { ref code, .. } => section = Some(section_name(code)),
+            ParserState::EndSection => section = None,
+            ParserState::ImportSectionEntry {
+                ty: ImportSectionEntryType::Function(_),
+                ..
+            } => imported_funcs += 1,
+            ParserState::BeginFunctionBody { .. } => {
+                func_index = Some(imported_funcs + defined_funcs);
+                defined_funcs += 1;
+            }
+            ParserState::EndFunctionBody => func_index = None,
+            _ => (),
+        }
+    }
+}
+
+/// Checks module `data`, which passed validation with `config`, uses no wasm
+/// proposals, which the compiler does not support yet.
+pub(crate) fn check_supported(data: &[u8], config: &Config) -> Result<(), UnsupportedFeature> {
+    let proposals = config.threads_enabled()
+        || config.reference_types_enabled()
+        || config.simd_enabled()
+        || config.bulk_memory_enabled();
+    if !proposals {
+        return Ok(());
+    }
+    validate(data, &Config::default()).map_err(UnsupportedFeature)
+}
@@ -1,21 +1,460 @@
 use crate::context::ContextToken;
+use crate::func::Func;
+use crate::host_module::HostModuleBuilder;
 use crate::instance::InstanceToken;
-use std::collections::HashSet;
+use crate::scratch_fs::ScratchFs;
+use crate::wasi_hooks::{override_clock_random, WasiClock};
+use crate::wasi_policy::{apply_policy, DirRights, WasiCategory, WasiDenial, WasiPolicy};
+use rand_chacha::ChaChaRng;
+use rand_core::{RngCore, SeedableRng};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::env;
 use std::fs::File;
-use wasmtime_wasi::instantiate_wasi;
-
-pub fn create_wasi(
-    mut context: ContextToken,
-    preopen_dirs: &[(String, File)],
-    argv: &[String],
-    environ: &[(String, String)],
-) -> InstanceToken {
-    let global_exports = context.context().get_global_exports();
-    let handle =
-        instantiate_wasi("", global_exports, &preopen_dirs, &argv, &environ).expect("wasi");
-
-    let mut contexts = HashSet::new();
-    contexts.insert(context);
-
-    InstanceToken::new(handle, contexts)
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::io::{FromRawFd, IntoRawFd};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::mem;
+use std::thread::{self, JoinHandle};
+use tempfile::NamedTempFile;
+use wasi_common::{preopen_dir, WasiCtxBuilder};
+use wasmtime_wasi::instantiate_wasi_with_context;
+
+#[derive(Fail, Debug)]
+pub enum WasiError {
+    #[fail(display = "Failed to preopen directory {}: {}", _0, _1)]
+    PreopenFailed(String, String),
+    #[fail(display = "Failed to set up WASI stdio: {}", _0)]
+    StdioFailed(String),
+    #[fail(display = "WASI stdio was moved into an instance built before")]
+    StdioConsumed,
+    #[fail(display = "WASI instantiation failed: {}", _0)]
+    InstantiationFailed(String),
+    #[fail(display = "Failed to apply WASI policy: {}", _0)]
+    PolicyFailed(String),
+}
+
+impl From<io::Error> for WasiError {
+    fn from(e: io::Error) -> WasiError {
+        WasiError::StdioFailed(e.to_string())
+    }
+}
+
+/// Functions replacing the wasi-common ones in the built instance, by name.
+pub(crate) type WasiOverrides = HashMap<String, Func>;
+
+/// Guest output collected by `WasiConfig::capture_stdout` or
+/// `WasiConfig::capture_stderr`.
+#[derive(Clone)]
+pub struct OutputBuffer(Rc<NamedTempFile>);
+
+impl OutputBuffer {
+    /// Returns everything the guest has written so far.
+    pub fn contents(&self) -> io::Result<Vec<u8>> {
+        let mut contents = Vec::new();
+        // Reopened to not move the offset the guest writes at.
+        self.0.reopen()?.read_to_end(&mut contents)?;
+        Ok(contents)
+    }
+}
+
+enum Stdio {
+    Inherit,
+    File(File),
+    Bytes(Vec<u8>),
+    Reader(Box<dyn Read + Send>),
+    Writer(Box<dyn Write + Send>),
+    Capture(OutputBuffer),
+    // Moved into a built instance.
+    Consumed,
+}
+
+impl Default for Stdio {
+    fn default() -> Stdio {
+        Stdio::Inherit
+    }
+}
+
+/// Waits for the threads forwarding the guest output to writers, once the
+/// guest side of their pipes is closed.
+struct OutputPumps(Vec<JoinHandle<io::Result<u64>>>);
+
+impl Drop for OutputPumps {
+    fn drop(&mut self) {
+        for pump in self.0.drain(..) {
+            let _ = pump.join();
+        }
+    }
+}
+
+/// Host end of the pipe a reader or writer stream is pumped through.
+enum PipeEnd {
+    Input(os_pipe::PipeWriter),
+    Output(os_pipe::PipeReader),
+}
+
+impl Stdio {
+    /// Opens the file for the WASI descriptor `fd`, with the host end of the
+    /// pipe of a reader or writer. The stream stays in place, to be used
+    /// again if building the instance fails, until `commit`.
+    fn open(&self, fd: u32) -> Result<(File, Option<PipeEnd>), WasiError> {
+        let file = match self {
+            Stdio::Inherit => unsafe {
+                match fd {
+                    0 => File::from_raw_fd(os_pipe::dup_stdin()?.into_raw_fd()),
+                    1 => File::from_raw_fd(os_pipe::dup_stdout()?.into_raw_fd()),
+                    _ => File::from_raw_fd(os_pipe::dup_stderr()?.into_raw_fd()),
+                }
+            },
+            Stdio::File(file) => file.try_clone()?,
+            Stdio::Bytes(bytes) => {
+                let mut file = tempfile::tempfile()?;
+                file.write_all(bytes)?;
+                file.seek(SeekFrom::Start(0))?;
+                file
+            }
+            Stdio::Reader(_) => {
+                let (pipe_reader, pipe_writer) = os_pipe::pipe()?;
+                let file = unsafe { File::from_raw_fd(pipe_reader.into_raw_fd()) };
+                return Ok((file, Some(PipeEnd::Input(pipe_writer))));
+            }
+            Stdio::Writer(_) => {
+                let (pipe_reader, pipe_writer) = os_pipe::pipe()?;
+                let file = unsafe { File::from_raw_fd(pipe_writer.into_raw_fd()) };
+                return Ok((file, Some(PipeEnd::Output(pipe_reader))));
+            }
+            Stdio::Capture(buffer) => buffer.0.as_file().try_clone()?,
+            Stdio::Consumed => return Err(WasiError::StdioConsumed),
+        };
+        Ok((file, None))
+    }
+
+    /// Moves the stream into the built instance, the inherited stdio can be
+    /// used again. Readers and writers are pumped through `pipe` by a helper
+    /// thread, the ones of writers are added to `pumps`. Reader threads are
+    /// not waited for, the reader may block.
+    fn commit(&mut self, pipe: Option<PipeEnd>, pumps: &mut OutputPumps) {
+        let stream = match self {
+            Stdio::Inherit => return,
+            _ => mem::replace(self, Stdio::Consumed),
+        };
+        match (stream, pipe) {
+            (Stdio::Reader(mut reader), Some(PipeEnd::Input(mut pipe))) => {
+                // Ends at the end of `reader` or once the instance closes
+                // the pipe.
+                thread::spawn(move || io::copy(&mut reader, &mut pipe));
+            }
+            (Stdio::Writer(mut writer), Some(PipeEnd::Output(mut pipe))) => {
+                // Ends once the instance closes the pipe.
+                pumps.0.push(thread::spawn(move || io::copy(&mut pipe, &mut writer)));
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Builds the "wasi_unstable" import instance, e.g.
+///
+/// ```ignore
+/// let wasi = WasiConfig::new()
+///     .arg("hello")
+///     .inherit_env_filtered(|name| name.starts_with("APP_"))
+///     .map_dir("/tmp", "/var/tmp")
+///     .build(&context)?;
+/// ```
+#[derive(Default)]
+pub struct WasiConfig {
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+    inherit_env: Option<Box<dyn Fn(&str) -> bool>>,
+    preopens: Vec<(String, PathBuf, DirRights)>,
+    stdin: Stdio,
+    stdout: Stdio,
+    stderr: Stdio,
+    clock: Option<Rc<dyn WasiClock>>,
+    random: Option<Rc<RefCell<dyn RngCore>>>,
+    denied: HashSet<WasiCategory>,
+    audit: Option<Rc<dyn Fn(&WasiDenial)>>,
+}
+
+impl WasiConfig {
+    pub fn new() -> WasiConfig {
+        WasiConfig::default()
+    }
+
+    /// Appends argument, the first one is the program name.
+    pub fn arg<S: Into<String>>(&mut self, arg: S) -> &mut WasiConfig {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn args<I, S>(&mut self, args: I) -> &mut WasiConfig
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Sets environment variable, it overrides the inherited one.
+    pub fn env<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) -> &mut WasiConfig {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
+    /// Passes all environment variables of the host process.
+    pub fn inherit_env(&mut self) -> &mut WasiConfig {
+        self.inherit_env_filtered(|_| true)
+    }
+
+    /// Passes environment variables of the host process, which names are
+    /// accepted by `filter`.
+    pub fn inherit_env_filtered<F>(&mut self, filter: F) -> &mut WasiConfig
+    where
+        F: Fn(&str) -> bool + 'static,
+    {
+        self.inherit_env = Some(Box::new(filter));
+        self
+    }
+
+    /// Preopens host directory `dir` under the same path for the guest.
+    pub fn preopen_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut WasiConfig {
+        self.preopen_dir_with_rights(dir, DirRights::all())
+    }
+
+    pub fn preopen_dir_with_rights<P: AsRef<Path>>(
+        &mut self,
+        dir: P,
+        rights: DirRights,
+    ) -> &mut WasiConfig {
+        let dir = dir.as_ref();
+        self.map_dir_with_rights(dir.to_string_lossy(), dir, rights)
+    }
+
+    /// Preopens host directory `host_dir` as `guest_dir` for the guest.
+    pub fn map_dir<G: Into<String>, H: AsRef<Path>>(
+        &mut self,
+        guest_dir: G,
+        host_dir: H,
+    ) -> &mut WasiConfig {
+        self.map_dir_with_rights(guest_dir, host_dir, DirRights::all())
+    }
+
+    /// Preopens host directory `host_dir` as `guest_dir`, limiting what the
+    /// guest can do in it to `rights`, e.g. `DirRights::read_only()`.
+    pub fn map_dir_with_rights<G: Into<String>, H: AsRef<Path>>(
+        &mut self,
+        guest_dir: G,
+        host_dir: H,
+        rights: DirRights,
+    ) -> &mut WasiConfig {
+        self.preopens
+            .push((guest_dir.into(), host_dir.as_ref().to_owned(), rights));
+        self
+    }
+
+    /// Preopens `fs` as `guest_dir` for the guest.
+    pub fn preopen_scratch<G: Into<String>>(
+        &mut self,
+        guest_dir: G,
+        fs: &ScratchFs,
+    ) -> &mut WasiConfig {
+        self.map_dir(guest_dir, fs.root())
+    }
+
+    pub fn preopen_scratch_with_rights<G: Into<String>>(
+        &mut self,
+        guest_dir: G,
+        fs: &ScratchFs,
+        rights: DirRights,
+    ) -> &mut WasiConfig {
+        self.map_dir_with_rights(guest_dir, fs.root(), rights)
+    }
+
+    /// Uses `file` as the guest stdin. By default the stdio is inherited
+    /// from the host process.
+    pub fn stdin_file(&mut self, file: File) -> &mut WasiConfig {
+        self.stdin = Stdio::File(file);
+        self
+    }
+
+    /// Feeds `bytes` to the guest stdin.
+    pub fn stdin_bytes<B: Into<Vec<u8>>>(&mut self, bytes: B) -> &mut WasiConfig {
+        self.stdin = Stdio::Bytes(bytes.into());
+        self
+    }
+
+    /// Feeds the guest stdin from `reader`.
+    pub fn stdin_reader<R: Read + Send + 'static>(&mut self, reader: R) -> &mut WasiConfig {
+        self.stdin = Stdio::Reader(Box::new(reader));
+        self
+    }
+
+    pub fn stdout_file(&mut self, file: File) -> &mut WasiConfig {
+        self.stdout = Stdio::File(file);
+        self
+    }
+
+    /// Forwards the guest stdout to `writer`.
+    pub fn stdout_writer<W: Write + Send + 'static>(&mut self, writer: W) -> &mut WasiConfig {
+        self.stdout = Stdio::Writer(Box::new(writer));
+        self
+    }
+
+    /// Collects the guest stdout into the returned buffer.
+    pub fn capture_stdout(&mut self) -> Result<OutputBuffer, WasiError> {
+        let buffer = OutputBuffer(Rc::new(NamedTempFile::new()?));
+        self.stdout = Stdio::Capture(buffer.clone());
+        Ok(buffer)
+    }
+
+    pub fn stderr_file(&mut self, file: File) -> &mut WasiConfig {
+        self.stderr = Stdio::File(file);
+        self
+    }
+
+    /// Forwards the guest stderr to `writer`.
+    pub fn stderr_writer<W: Write + Send + 'static>(&mut self, writer: W) -> &mut WasiConfig {
+        self.stderr = Stdio::Writer(Box::new(writer));
+        self
+    }
+
+    /// Collects the guest stderr into the returned buffer.
+    pub fn capture_stderr(&mut self) -> Result<OutputBuffer, WasiError> {
+        let buffer = OutputBuffer(Rc::new(NamedTempFile::new()?));
+        self.stderr = Stdio::Capture(buffer.clone());
+        Ok(buffer)
+    }
+
+    /// Serves the guest `clock_time_get` from `clock` instead of the host
+    /// clocks, e.g. `VirtualClock` for reproducible runs.
+    pub fn clock<C: WasiClock + 'static>(&mut self, clock: C) -> &mut WasiConfig {
+        self.clock = Some(Rc::new(clock));
+        self
+    }
+
+    /// Serves the guest `random_get` from `random` instead of the host
+    /// entropy.
+    pub fn random<R: RngCore + 'static>(&mut self, random: R) -> &mut WasiConfig {
+        self.random = Some(Rc::new(RefCell::new(random)));
+        self
+    }
+
+    /// Serves the guest `random_get` from a ChaCha generator seeded with
+    /// `seed`, the same sequence for the same seed.
+    pub fn random_seed(&mut self, seed: u64) -> &mut WasiConfig {
+        self.random(ChaChaRng::seed_from_u64(seed))
+    }
+
+    /// Fails all calls of the `category` functions with `ENOTCAPABLE`.
+    pub fn deny(&mut self, category: WasiCategory) -> &mut WasiConfig {
+        self.denied.insert(category);
+        self
+    }
+
+    /// Reports every call denied by `deny` or by the preopen rights to
+    /// `audit`.
+    pub fn audit<F: Fn(&WasiDenial) + 'static>(&mut self, audit: F) -> &mut WasiConfig {
+        self.audit = Some(Rc::new(audit));
+        self
+    }
+
+    fn environ(&self) -> Vec<(String, String)> {
+        let mut environ: Vec<(String, String)> = match self.inherit_env {
+            Some(ref filter) => env::vars()
+                .filter(|(key, _)| filter(key))
+                .filter(|(key, _)| self.env.iter().all(|(k, _)| k != key))
+                .collect(),
+            None => Vec::new(),
+        };
+        environ.extend(self.env.iter().cloned());
+        environ
+    }
+
+    fn preopen_dirs(&self) -> Result<Vec<(String, File)>, WasiError> {
+        self.preopens
+            .iter()
+            .map(|(guest_dir, host_dir, _)| {
+                let file = preopen_dir(host_dir).map_err(|e| {
+                    WasiError::PreopenFailed(host_dir.display().to_string(), e.to_string())
+                })?;
+                Ok((guest_dir.clone(), file))
+            })
+            .collect()
+    }
+
+    /// Creates the WASI instance in `context`, which shall be also used to
+    /// instantiate the modules importing it. The stdio streams other than
+    /// the inherited ones are moved into the instance, building again fails
+    /// with `WasiError::StdioConsumed` unless they are set anew. Output
+    /// forwarded to writers is complete once `context` is dropped.
+    pub fn build(&mut self, context: &ContextToken) -> Result<InstanceToken, WasiError> {
+        self.build_with(context, WasiOverrides::new())
+    }
+
+    /// Builds the instance as `build`, with the functions of `overrides`
+    /// replacing the wasi-common ones unless they are denied.
+    pub(crate) fn build_with(
+        &mut self,
+        context: &ContextToken,
+        mut overrides: WasiOverrides,
+    ) -> Result<InstanceToken, WasiError> {
+        let preopen_dirs = self.preopen_dirs()?;
+        let environ = self.environ();
+
+        let (stdin, stdin_pipe) = self.stdin.open(0)?;
+        let (stdout, stdout_pipe) = self.stdout.open(1)?;
+        let (stderr, stderr_pipe) = self.stderr.open(2)?;
+
+        let mut builder = WasiCtxBuilder::new()
+            .args(&self.args)
+            .envs(&environ)
+            .stdin(stdin)
+            .stdout(stdout)
+            .stderr(stderr);
+        for (guest_dir, dir) in preopen_dirs {
+            builder = builder.preopened_dir(dir, guest_dir);
+        }
+        let wasi_ctx = builder
+            .build()
+            .map_err(|e| WasiError::InstantiationFailed(e.to_string()))?;
+
+        let mut context = context.clone();
+        let global_exports = context.context().get_global_exports();
+        let handle = instantiate_wasi_with_context("", global_exports, wasi_ctx)
+            .map_err(|e| WasiError::InstantiationFailed(e.to_string()))?;
+        let mut contexts = HashSet::new();
+        contexts.insert(context.clone());
+        let wasi = InstanceToken::new(handle, contexts);
+
+        override_clock_random(&mut overrides, &context, self.clock.clone(), self.random.clone());
+        let policy = WasiPolicy::new(
+            self.denied.clone(),
+            self.preopens.iter().map(|(_, _, rights)| *rights).collect(),
+            self.audit.clone(),
+        );
+        if !policy.is_permissive() {
+            apply_policy(&mut overrides, &wasi, &context, policy)
+                .map_err(|e| WasiError::PolicyFailed(e.to_string()))?;
+        }
+
+        // Nothing fails any more: move the stdio into the instance. The
+        // pipes are closed when the context drops the instance.
+        let mut pumps = OutputPumps(Vec::new());
+        self.stdin.commit(stdin_pipe, &mut pumps);
+        self.stdout.commit(stdout_pipe, &mut pumps);
+        self.stderr.commit(stderr_pipe, &mut pumps);
+        context.attach(pumps);
+
+        if overrides.is_empty() {
+            return Ok(wasi);
+        }
+        let mut builder = HostModuleBuilder::new();
+        for (name, func) in &overrides {
+            builder.func(name, func.clone());
+        }
+        Ok(builder.reexport(&wasi).build())
+    }
 }
@@ -0,0 +1,152 @@
+use crate::context::ContextToken;
+use crate::func::Func;
+use crate::types::{FuncType, ValType};
+use crate::wasi::WasiOverrides;
+use rand_core::RngCore;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::slice;
+use wasmtime_jit::RuntimeValue;
+use wasmtime_runtime::Export;
+
+const ESUCCESS: i32 = 0;
+const EFAULT: i32 = 21;
+const EINVAL: i32 = 28;
+
+/// WASI clock identifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockId {
+    Realtime,
+    Monotonic,
+    ProcessCputime,
+    ThreadCputime,
+}
+
+impl ClockId {
+    fn from_raw(id: i32) -> Option<ClockId> {
+        match id {
+            0 => Some(ClockId::Realtime),
+            1 => Some(ClockId::Monotonic),
+            2 => Some(ClockId::ProcessCputime),
+            3 => Some(ClockId::ThreadCputime),
+            _ => None,
+        }
+    }
+}
+
+/// Time source for the guest `clock_time_get`, see `WasiConfig::clock`.
+pub trait WasiClock {
+    /// Returns time of clock `id` in nanoseconds, `None` if it is not
+    /// supported.
+    fn time(&self, id: ClockId) -> Option<u64>;
+}
+
+/// Clock starting at `start` and advancing by `step` nanoseconds on every
+/// read, the same for all clock ids.
+pub struct VirtualClock {
+    now: Cell<u64>,
+    step: u64,
+}
+
+impl VirtualClock {
+    pub fn new(start: u64, step: u64) -> VirtualClock {
+        VirtualClock {
+            now: Cell::new(start),
+            step,
+        }
+    }
+}
+
+impl WasiClock for VirtualClock {
+    fn time(&self, _id: ClockId) -> Option<u64> {
+        let now = self.now.get();
+        self.now.set(now.wrapping_add(self.step));
+        Some(now)
+    }
+}
+
+/// Linear memory exported as "memory" by the module instantiated in the
+/// context, i.e. by the caller of the WASI functions.
+pub(crate) struct GuestMemory(Rc<RefCell<HashMap<String, Option<Export>>>>);
+
+impl GuestMemory {
+    pub fn new(context: &ContextToken) -> GuestMemory {
+        GuestMemory(context.clone().context().get_global_exports())
+    }
+
+    /// Calls `f` with the `len` bytes at `ptr`, returns `None` if they are
+    /// out of bounds. The bytes are borrowed only during the call, `f` must
+    /// not run wasm code, which could grow the memory.
+    pub fn with_slice_mut<R, F>(&mut self, ptr: i32, len: i32, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let definition = match self.0.borrow().get("memory") {
+            Some(Some(Export::Memory { definition, .. })) => *definition,
+            _ => return None,
+        };
+        let (ptr, len) = (ptr as u32 as usize, len as u32 as usize);
+        let bytes = unsafe {
+            if ptr.checked_add(len)? > (*definition).current_length {
+                return None;
+            }
+            slice::from_raw_parts_mut((*definition).base.add(ptr), len)
+        };
+        Some(f(bytes))
+    }
+}
+
+/// Serves `clock_time_get` and `random_get` from the embedder provided
+/// `clock` and `random` source, if any.
+pub(crate) fn override_clock_random(
+    overrides: &mut WasiOverrides,
+    context: &ContextToken,
+    clock: Option<Rc<dyn WasiClock>>,
+    random: Option<Rc<RefCell<dyn RngCore>>>,
+) {
+    if let Some(clock) = clock {
+        let memory = RefCell::new(GuestMemory::new(context));
+        let ty = FuncType::new(vec![ValType::I32, ValType::I64, ValType::I32], vec![ValType::I32]);
+        overrides.insert(
+            "clock_time_get".to_owned(),
+            Func::new(ty, move |args| {
+                let errno = match (args[0], args[2]) {
+                    (RuntimeValue::I32(id), RuntimeValue::I32(ptr)) => {
+                        clock_time_get(&*clock, &mut memory.borrow_mut(), id, ptr)
+                    }
+                    _ => EINVAL,
+                };
+                Ok(vec![RuntimeValue::I32(errno)])
+            }),
+        );
+    }
+
+    if let Some(random) = random {
+        let memory = RefCell::new(GuestMemory::new(context));
+        let ty = FuncType::new(vec![ValType::I32, ValType::I32], vec![ValType::I32]);
+        overrides.insert(
+            "random_get".to_owned(),
+            Func::new(ty, move |args| {
+                let errno = match (args[0], args[1]) {
+                    (RuntimeValue::I32(ptr), RuntimeValue::I32(len)) => memory
+                        .borrow_mut()
+                        .with_slice_mut(ptr, len, |buf| random.borrow_mut().fill_bytes(buf))
+                        .map_or(EFAULT, |()| ESUCCESS),
+                    _ => EINVAL,
+                };
+                Ok(vec![RuntimeValue::I32(errno)])
+            }),
+        );
+    }
+}
+
+fn clock_time_get(clock: &dyn WasiClock, memory: &mut GuestMemory, id: i32, ptr: i32) -> i32 {
+    let time = match ClockId::from_raw(id).and_then(|id| clock.time(id)) {
+        Some(time) => time,
+        None => return EINVAL,
+    };
+    memory
+        .with_slice_mut(ptr, 8, |buf| buf.copy_from_slice(&time.to_le_bytes()))
+        .map_or(EFAULT, |()| ESUCCESS)
+}
@@ -0,0 +1,513 @@
+use crate::context::ContextToken;
+use crate::func::Func;
+use crate::instance::InstanceToken;
+use crate::trap::Trap;
+use crate::types::ValType;
+use crate::wasi::WasiOverrides;
+use crate::wasi_hooks::GuestMemory;
+use failure::Error;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use wasmtime_environ::Export;
+use wasmtime_jit::RuntimeValue;
+
+const ESUCCESS: i32 = 0;
+const EBADF: i32 = 8;
+const ENOTCAPABLE: i32 = 76;
+
+// wasi-common numbers the preopened directories after stdio in order of
+// preopening. Later the descriptors are followed through `path_open`,
+// `fd_renumber` and `fd_close`.
+const FIRST_PREOPEN_FD: u32 = 3;
+
+const OFLAGS_CREAT: i32 = 1 << 0;
+const OFLAGS_TRUNC: i32 = 1 << 3;
+
+const RIGHT_FD_DATASYNC: u64 = 1 << 0;
+const RIGHT_FD_WRITE: u64 = 1 << 6;
+const RIGHT_FD_ALLOCATE: u64 = 1 << 8;
+const RIGHT_PATH_CREATE_DIRECTORY: u64 = 1 << 9;
+const RIGHT_PATH_CREATE_FILE: u64 = 1 << 10;
+const RIGHT_PATH_LINK_TARGET: u64 = 1 << 12;
+const RIGHT_PATH_RENAME_SOURCE: u64 = 1 << 16;
+const RIGHT_PATH_RENAME_TARGET: u64 = 1 << 17;
+const RIGHT_PATH_FILESTAT_SET_SIZE: u64 = 1 << 19;
+const RIGHT_PATH_FILESTAT_SET_TIMES: u64 = 1 << 20;
+const RIGHT_FD_FILESTAT_SET_SIZE: u64 = 1 << 22;
+const RIGHT_FD_FILESTAT_SET_TIMES: u64 = 1 << 23;
+const RIGHT_PATH_SYMLINK: u64 = 1 << 24;
+const RIGHT_PATH_REMOVE_DIRECTORY: u64 = 1 << 25;
+const RIGHT_PATH_UNLINK_FILE: u64 = 1 << 26;
+
+/// Rights of a preopened directory and everything opened through it, see
+/// `WasiConfig::map_dir_with_rights`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirRights {
+    create: bool,
+    delete: bool,
+    write: bool,
+}
+
+impl DirRights {
+    pub fn all() -> DirRights {
+        DirRights {
+            create: true,
+            delete: true,
+            write: true,
+        }
+    }
+
+    /// Allows only reading files and listing directories.
+    pub fn read_only() -> DirRights {
+        DirRights {
+            create: false,
+            delete: false,
+            write: false,
+        }
+    }
+
+    /// Denies creating files, directories and links.
+    pub fn no_create(mut self) -> DirRights {
+        self.create = false;
+        self
+    }
+
+    /// Denies removing and renaming files and directories.
+    pub fn no_delete(mut self) -> DirRights {
+        self.delete = false;
+        self
+    }
+
+    /// WASI rights taken away from the descriptors opened in the directory.
+    fn removed(&self) -> u64 {
+        let mut removed = 0;
+        if !self.create {
+            removed |= RIGHT_PATH_CREATE_DIRECTORY
+                | RIGHT_PATH_CREATE_FILE
+                | RIGHT_PATH_LINK_TARGET
+                | RIGHT_PATH_RENAME_TARGET
+                | RIGHT_PATH_SYMLINK;
+        }
+        if !self.delete {
+            removed |= RIGHT_PATH_REMOVE_DIRECTORY
+                | RIGHT_PATH_UNLINK_FILE
+                | RIGHT_PATH_RENAME_SOURCE;
+        }
+        if !self.write {
+            removed |= RIGHT_FD_WRITE
+                | RIGHT_FD_DATASYNC
+                | RIGHT_FD_ALLOCATE
+                | RIGHT_FD_FILESTAT_SET_SIZE
+                | RIGHT_FD_FILESTAT_SET_TIMES
+                | RIGHT_PATH_FILESTAT_SET_SIZE
+                | RIGHT_PATH_FILESTAT_SET_TIMES;
+        }
+        removed
+    }
+}
+
+impl Default for DirRights {
+    fn default() -> DirRights {
+        DirRights::all()
+    }
+}
+
+/// Group of WASI functions that can be denied with `WasiConfig::deny`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WasiCategory {
+    /// `path_*` functions, preopens discovery and `fd_readdir`. The guest
+    /// still can use stdio.
+    Filesystem,
+    /// `clock_res_get` and `clock_time_get`.
+    Clocks,
+    /// `random_get`.
+    Random,
+    /// `environ_get` and `environ_sizes_get`.
+    Environment,
+}
+
+impl WasiCategory {
+    fn of(name: &str) -> Option<WasiCategory> {
+        match name {
+            "clock_res_get" | "clock_time_get" => Some(WasiCategory::Clocks),
+            "random_get" => Some(WasiCategory::Random),
+            "environ_get" | "environ_sizes_get" => Some(WasiCategory::Environment),
+            "fd_prestat_get" | "fd_prestat_dir_name" | "fd_readdir" => {
+                Some(WasiCategory::Filesystem)
+            }
+            _ if name.starts_with("path_") => Some(WasiCategory::Filesystem),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DenialReason {
+    /// The function belongs to a denied category.
+    Category(WasiCategory),
+    /// The call needs rights the directory `fd`, a restricted preopen or
+    /// opened through one, does not have.
+    Rights { fd: u32 },
+}
+
+/// Denied WASI call, reported to `WasiConfig::audit` callback.
+#[derive(Debug)]
+pub struct WasiDenial<'a> {
+    pub function: &'a str,
+    pub reason: DenialReason,
+}
+
+pub(crate) struct WasiPolicy {
+    denied: HashSet<WasiCategory>,
+    // Rights of the descriptors of restricted preopens and of the ones
+    // opened through them.
+    fd_rights: RefCell<HashMap<u32, DirRights>>,
+    audit: Option<Rc<dyn Fn(&WasiDenial)>>,
+}
+
+impl WasiPolicy {
+    /// Creates policy denying the `denied` categories, with the preopens
+    /// having `preopen_rights` in preopen order.
+    pub fn new(
+        denied: HashSet<WasiCategory>,
+        preopen_rights: Vec<DirRights>,
+        audit: Option<Rc<dyn Fn(&WasiDenial)>>,
+    ) -> WasiPolicy {
+        let policy = WasiPolicy {
+            denied,
+            fd_rights: RefCell::new(HashMap::new()),
+            audit,
+        };
+        for (i, rights) in preopen_rights.into_iter().enumerate() {
+            policy.set_rights(FIRST_PREOPEN_FD + i as u32, rights);
+        }
+        policy
+    }
+
+    pub fn is_permissive(&self) -> bool {
+        self.denied.is_empty() && !self.restricts_preopens()
+    }
+
+    fn restricts_preopens(&self) -> bool {
+        !self.fd_rights.borrow().is_empty()
+    }
+
+    fn rights(&self, fd: i32) -> DirRights {
+        self.fd_rights
+            .borrow()
+            .get(&(fd as u32))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn set_rights(&self, fd: u32, rights: DirRights) {
+        let mut fd_rights = self.fd_rights.borrow_mut();
+        if rights == DirRights::all() {
+            fd_rights.remove(&fd);
+        } else {
+            fd_rights.insert(fd, rights);
+        }
+    }
+
+    /// Follows the descriptor `from` renumbered to `to`, closing `to`.
+    fn renumber(&self, from: u32, to: u32) {
+        let mut fd_rights = self.fd_rights.borrow_mut();
+        fd_rights.remove(&to);
+        if let Some(rights) = fd_rights.remove(&from) {
+            fd_rights.insert(to, rights);
+        }
+    }
+
+    fn close(&self, fd: u32) {
+        self.fd_rights.borrow_mut().remove(&fd);
+    }
+
+    fn deny(&self, function: &str, reason: DenialReason) -> i32 {
+        if let Some(ref audit) = self.audit {
+            audit(&WasiDenial { function, reason });
+        }
+        match (function, reason) {
+            // Lets the guest see no preopens rather than fail its startup.
+            ("fd_prestat_get", DenialReason::Category(_)) => EBADF,
+            _ => ENOTCAPABLE,
+        }
+    }
+
+    /// Checks the call `function` on directory `fd` is allowed by `allowed`,
+    /// returns the errno to fail the call with otherwise.
+    fn check(&self, function: &str, fd: i32, allowed: fn(&DirRights) -> bool) -> Option<i32> {
+        if allowed(&self.rights(fd)) {
+            None
+        } else {
+            Some(self.deny(function, DenialReason::Rights { fd: fd as u32 }))
+        }
+    }
+}
+
+fn i32_arg(args: &[RuntimeValue], i: usize) -> i32 {
+    match args[i] {
+        RuntimeValue::I32(value) => value,
+        _ => panic!("i32 argument expected"),
+    }
+}
+
+fn i64_arg(args: &[RuntimeValue], i: usize) -> i64 {
+    match args[i] {
+        RuntimeValue::I64(value) => value,
+        _ => panic!("i64 argument expected"),
+    }
+}
+
+fn forward_result(result: Result<i32, Error>) -> Result<Vec<RuntimeValue>, Trap> {
+    match result {
+        Ok(errno) => Ok(vec![RuntimeValue::I32(errno)]),
+        Err(e) => Err(match e.downcast::<Trap>() {
+            Ok(trap) => trap,
+            Err(e) => Trap::new(e.to_string()),
+        }),
+    }
+}
+
+/// Adds overrides of the `wasi` functions denying the calls not allowed by
+/// `policy`. Denied functions replace the ones already overridden.
+pub(crate) fn apply_policy(
+    overrides: &mut WasiOverrides,
+    wasi: &InstanceToken,
+    context: &ContextToken,
+    policy: WasiPolicy,
+) -> Result<(), Error> {
+    let policy = Rc::new(policy);
+
+    let names: Vec<String> = wasi
+        .handle()
+        .module_ref()
+        .exports
+        .iter()
+        .filter(|(_, export)| match export {
+            Export::Function(_) => true,
+            _ => false,
+        })
+        .map(|(name, _)| name.clone())
+        .collect();
+    for name in names {
+        let category = match WasiCategory::of(&name) {
+            Some(category) if policy.denied.contains(&category) => category,
+            _ => continue,
+        };
+        let ty = Func::from_export(wasi, &name).expect("wasi function").ty();
+        let policy = policy.clone();
+        let function = name.clone();
+        let stub = Func::new(ty.clone(), move |_| {
+            let errno = policy.deny(&function, DenialReason::Category(category));
+            Ok(ty
+                .results()
+                .iter()
+                .map(|ty| match ty {
+                    ValType::I32 => RuntimeValue::I32(errno),
+                    ValType::I64 => RuntimeValue::I64(errno as i64),
+                    ValType::F32 => RuntimeValue::F32(0),
+                    ValType::F64 => RuntimeValue::F64(0),
+                })
+                .collect())
+        });
+        overrides.insert(name, stub);
+    }
+
+    if policy.restricts_preopens() && !policy.denied.contains(&WasiCategory::Filesystem) {
+        restrict_preopens(overrides, wasi, context, &policy)?;
+    }
+    Ok(())
+}
+
+/// Overrides the functions operating on directories, checking the rights
+/// of the restricted preopens and of the directories opened through them.
+/// The descriptors opened through a restricted directory get its rights
+/// masked, so wasi-common enforces them for the files as well.
+fn restrict_preopens(
+    overrides: &mut WasiOverrides,
+    wasi: &InstanceToken,
+    context: &ContextToken,
+    policy: &Rc<WasiPolicy>,
+) -> Result<(), Error> {
+    // The overrides end up named in the context, so they must not hold the
+    // context through the token of `wasi`. wasi-common has no compiled code
+    // to keep alive.
+    let wasi = &InstanceToken::from_handle(wasi.handle().clone());
+    let path_open =
+        wasi.get_typed_func::<(i32, i32, i32, i32, i32, i64, i64, i32, i32), i32>("path_open")?;
+    let memory = RefCell::new(GuestMemory::new(context));
+    let p = policy.clone();
+    overrides.insert(
+        "path_open".to_owned(),
+        Func::new(path_open.ty(), move |args| {
+            let (fd, oflags, rights_base) = (i32_arg(args, 0), i32_arg(args, 4), i64_arg(args, 5));
+            let rights = p.rights(fd);
+            if oflags & OFLAGS_CREAT != 0 && !rights.create
+                || oflags & OFLAGS_TRUNC != 0 && !rights.write
+                || rights_base as u64 & RIGHT_FD_WRITE != 0 && !rights.write
+            {
+                let errno = p.deny("path_open", DenialReason::Rights { fd: fd as u32 });
+                return Ok(vec![RuntimeValue::I32(errno)]);
+            }
+            let removed = rights.removed() as i64;
+            let opened_ptr = i32_arg(args, 8);
+            let errno = path_open.call((
+                fd,
+                i32_arg(args, 1),
+                i32_arg(args, 2),
+                i32_arg(args, 3),
+                oflags,
+                rights_base & !removed,
+                i64_arg(args, 6) & !removed,
+                i32_arg(args, 7),
+                opened_ptr,
+            ));
+            if let Ok(ESUCCESS) = errno {
+                let opened = memory.borrow_mut().with_slice_mut(opened_ptr, 4, |bytes| {
+                    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+                });
+                if let Some(opened) = opened {
+                    p.set_rights(opened, rights);
+                }
+            }
+            forward_result(errno)
+        }),
+    );
+
+    for &(name, allowed) in &[
+        ("path_create_directory", (|r: &DirRights| r.create) as fn(&DirRights) -> bool),
+        ("path_unlink_file", |r: &DirRights| r.delete),
+        ("path_remove_directory", |r: &DirRights| r.delete),
+    ] {
+        let f = wasi.get_typed_func::<(i32, i32, i32), i32>(name)?;
+        let p = policy.clone();
+        overrides.insert(
+            name.to_owned(),
+            Func::new(f.ty(), move |args| {
+                let fd = i32_arg(args, 0);
+                if let Some(errno) = p.check(name, fd, allowed) {
+                    return Ok(vec![RuntimeValue::I32(errno)]);
+                }
+                forward_result(f.call((fd, i32_arg(args, 1), i32_arg(args, 2))))
+            }),
+        );
+    }
+
+    let path_rename = wasi.get_typed_func::<(i32, i32, i32, i32, i32, i32), i32>("path_rename")?;
+    let p = policy.clone();
+    overrides.insert(
+        "path_rename".to_owned(),
+        Func::new(path_rename.ty(), move |args| {
+            let (fd, new_fd) = (i32_arg(args, 0), i32_arg(args, 3));
+            let denied = p
+                .check("path_rename", fd, |r| r.delete)
+                .or_else(|| p.check("path_rename", new_fd, |r| r.create));
+            if let Some(errno) = denied {
+                return Ok(vec![RuntimeValue::I32(errno)]);
+            }
+            forward_result(path_rename.call((
+                fd,
+                i32_arg(args, 1),
+                i32_arg(args, 2),
+                new_fd,
+                i32_arg(args, 4),
+                i32_arg(args, 5),
+            )))
+        }),
+    );
+
+    let path_link = wasi.get_typed_func::<(i32, i32, i32, i32, i32, i32, i32), i32>("path_link")?;
+    let p = policy.clone();
+    overrides.insert(
+        "path_link".to_owned(),
+        Func::new(path_link.ty(), move |args| {
+            let new_fd = i32_arg(args, 4);
+            if let Some(errno) = p.check("path_link", new_fd, |r| r.create) {
+                return Ok(vec![RuntimeValue::I32(errno)]);
+            }
+            forward_result(path_link.call((
+                i32_arg(args, 0),
+                i32_arg(args, 1),
+                i32_arg(args, 2),
+                i32_arg(args, 3),
+                new_fd,
+                i32_arg(args, 5),
+                i32_arg(args, 6),
+            )))
+        }),
+    );
+
+    let path_symlink = wasi.get_typed_func::<(i32, i32, i32, i32, i32), i32>("path_symlink")?;
+    let p = policy.clone();
+    overrides.insert(
+        "path_symlink".to_owned(),
+        Func::new(path_symlink.ty(), move |args| {
+            let fd = i32_arg(args, 2);
+            if let Some(errno) = p.check("path_symlink", fd, |r| r.create) {
+                return Ok(vec![RuntimeValue::I32(errno)]);
+            }
+            forward_result(path_symlink.call((
+                i32_arg(args, 0),
+                i32_arg(args, 1),
+                fd,
+                i32_arg(args, 3),
+                i32_arg(args, 4),
+            )))
+        }),
+    );
+
+    let set_times = wasi
+        .get_typed_func::<(i32, i32, i32, i32, i64, i64, i32), i32>("path_filestat_set_times")?;
+    let p = policy.clone();
+    overrides.insert(
+        "path_filestat_set_times".to_owned(),
+        Func::new(set_times.ty(), move |args| {
+            let fd = i32_arg(args, 0);
+            if let Some(errno) = p.check("path_filestat_set_times", fd, |r| r.write) {
+                return Ok(vec![RuntimeValue::I32(errno)]);
+            }
+            forward_result(set_times.call((
+                fd,
+                i32_arg(args, 1),
+                i32_arg(args, 2),
+                i32_arg(args, 3),
+                i64_arg(args, 4),
+                i64_arg(args, 5),
+                i32_arg(args, 6),
+            )))
+        }),
+    );
+
+    // The rights move with the renumbered descriptor and are dropped with
+    // the closed one, whose number may be reused.
+    let fd_renumber = wasi.get_typed_func::<(i32, i32), i32>("fd_renumber")?;
+    let p = policy.clone();
+    overrides.insert(
+        "fd_renumber".to_owned(),
+        Func::new(fd_renumber.ty(), move |args| {
+            let (from, to) = (i32_arg(args, 0), i32_arg(args, 1));
+            let errno = fd_renumber.call((from, to));
+            if let Ok(ESUCCESS) = errno {
+                p.renumber(from as u32, to as u32);
+            }
+            forward_result(errno)
+        }),
+    );
+
+    let fd_close = wasi.get_typed_func::<(i32,), i32>("fd_close")?;
+    let p = policy.clone();
+    overrides.insert(
+        "fd_close".to_owned(),
+        Func::new(fd_close.ty(), move |args| {
+            let fd = i32_arg(args, 0);
+            let errno = fd_close.call((fd,));
+            if let Ok(ESUCCESS) = errno {
+                p.close(fd as u32);
+            }
+            forward_result(errno)
+        }),
+    );
+
+    Ok(())
+}
@@ -0,0 +1,71 @@
+use std::borrow::Cow;
+use std::str;
+
+const WASM_MAGIC: &[u8] = b"\0asm";
+
+#[derive(Fail, Debug)]
+#[fail(display = "WAT parse error at {}:{}: {}", line, column, message)]
+pub struct WatError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl WatError {
+    /// Extracts the position from the wabt message, which looks like
+    /// "test.wast:2:5: error: unexpected token ...".
+    fn from_wabt(message: &str) -> WatError {
+        let mut parts = message.splitn(4, ':').skip(1);
+        let line = parts.next().and_then(|s| s.trim().parse().ok());
+        let column = parts.next().and_then(|s| s.trim().parse().ok());
+        match (line, column, parts.next()) {
+            (Some(line), Some(column), Some(rest)) => WatError {
+                line,
+                column,
+                message: rest
+                    .lines()
+                    .next()
+                    .unwrap_or("")
+                    .trim()
+                    .trim_start_matches("error:")
+                    .trim()
+                    .to_owned(),
+            },
+            _ => WatError {
+                line: 0,
+                column: 0,
+                message: message.trim().to_owned(),
+            },
+        }
+    }
+}
+
+/// Translates the WebAssembly text format `wat` into binary.
+pub fn wat2wasm(wat: &str) -> Result<Vec<u8>, WatError> {
+    wabt::Wat2Wasm::new()
+        .convert(wat)
+        .map(|buf| buf.as_ref().to_vec())
+        .map_err(|e| match e.kind() {
+            wabt::ErrorKind::Parse(message) => WatError::from_wabt(message),
+            _ => WatError::from_wabt(&e.to_string()),
+        })
+}
+
+/// Returns `data` as is if it is a binary module, or translated from the
+/// text format otherwise.
+///
+/// Text never starts with a NUL byte, so data starting like the binary magic,
+/// even truncated or corrupted after the first byte, is taken as binary and
+/// its decode error is reported by the validation rather than by the text
+/// parser.
+pub(crate) fn to_binary(data: &[u8]) -> Result<Cow<[u8]>, WatError> {
+    if data.first() == WASM_MAGIC.first() {
+        return Ok(Cow::Borrowed(data));
+    }
+    let wat = str::from_utf8(data).map_err(|e| WatError {
+        line: 0,
+        column: 0,
+        message: e.to_string(),
+    })?;
+    Ok(Cow::Owned(wat2wasm(wat)?))
+}
@@ -0,0 +1,60 @@
+use wasmtime_embed::{
+    instantiate_reactor, run_command, AmbiguousWasiModule, ContextToken, ExitStatus, NotAReactor,
+    WasiConfig,
+};
+
+#[test]
+fn command_exit_status() {
+    let returns = r#"(module (func (export "_start")))"#;
+    let status = run_command(returns.as_bytes(), &mut WasiConfig::new()).unwrap();
+    assert!(status.success());
+
+    let exits = r#"(module
+      (import "wasi_unstable" "proc_exit" (func $proc_exit (param i32)))
+      (func (export "_start") (call $proc_exit (i32.const 3))))"#;
+    let status = run_command(exits.as_bytes(), &mut WasiConfig::new()).unwrap();
+    assert_eq!(status.code(), Some(3));
+
+    let traps = r#"(module (func (export "_start") unreachable))"#;
+    match run_command(traps.as_bytes(), &mut WasiConfig::new()).unwrap() {
+        ExitStatus::Trapped(_) => (),
+        status => panic!("unexpected status: {:?}", status),
+    }
+}
+
+#[test]
+fn reactor_is_initialized_once() {
+    let reactor = r#"(module
+      (global $count (mut i32) (i32.const 0))
+      (func (export "_initialize")
+        (global.set $count (i32.add (global.get $count) (i32.const 1))))
+      (func (export "count") (result i32) (global.get $count)))"#;
+    let instance = instantiate_reactor(reactor.as_bytes(), &mut WasiConfig::new()).unwrap();
+    let count = instance.get_typed_func::<(), i32>("count").unwrap();
+    assert_eq!(count.call(()).unwrap(), 1);
+}
+
+#[test]
+fn ambiguous_module_is_rejected_before_instantiation() {
+    // Instantiating the module would trap in its start function.
+    let ambiguous = r#"(module
+      (func $start unreachable)
+      (start $start)
+      (func (export "_start"))
+      (func (export "_initialize")))"#;
+    let mut wasi = WasiConfig::new();
+    wasi.stdin_bytes("input");
+    let error = instantiate_reactor(ambiguous.as_bytes(), &mut wasi).unwrap_err();
+    assert!(error.downcast::<AmbiguousWasiModule>().is_ok());
+    // The stdio was not moved into a WASI instance.
+    assert!(wasi.build(&ContextToken::create()).is_ok());
+}
+
+#[test]
+fn command_is_not_a_reactor() {
+    let command = r#"(module
+      (func (export "_start"))
+      (func (export "helper") (result i32) (i32.const 1)))"#;
+    let error = instantiate_reactor(command.as_bytes(), &mut WasiConfig::new()).unwrap_err();
+    assert!(error.downcast::<NotAReactor>().is_ok());
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use wasmtime_embed::{
+    instantiate, instantiate_in_context, Config, ContextToken, FuelNotEnabled, InstanceToken,
+    Trap, TrapCode,
+};
+
+const LOOPS: &str = r#"(module
+  (func (export "spin") (loop (br 0)))
+  ;; Counts down from the argument, returns the iterations.
+  (func (export "count") (param i32) (result i32) (local i32)
+    (block
+      (loop
+        (br_if 1 (i32.eqz (local.get 0)))
+        (local.set 0 (i32.sub (local.get 0) (i32.const 1)))
+        (local.set 1 (i32.add (local.get 1) (i32.const 1)))
+        (br 0)))
+    (local.get 1)))"#;
+
+fn fueled(fuel: u64) -> InstanceToken {
+    let mut config = Config::new();
+    config.consume_fuel(true).initial_fuel(fuel);
+    let context = ContextToken::with_config(config);
+    instantiate_in_context(LOOPS.as_bytes(), HashMap::new(), context).unwrap()
+}
+
+fn out_of_fuel(result: Result<i32, failure::Error>) -> bool {
+    match result {
+        Err(e) => e.downcast::<Trap>().expect("Trap").code() == TrapCode::OutOfFuel,
+        Ok(_) => false,
+    }
+}
+
+#[test]
+fn infinite_loop_runs_out_of_fuel() {
+    let instance = fueled(10_000);
+    let spin = instance.get_typed_func::<(), ()>("spin").unwrap();
+    let trap = spin.call(()).unwrap_err().downcast::<Trap>().expect("Trap");
+    assert_eq!(trap.code(), TrapCode::OutOfFuel);
+    assert_eq!(instance.fuel_remaining(), Some(0));
+}
+
+#[test]
+fn added_fuel_lets_instance_run_again() {
+    let instance = fueled(100);
+    let count = instance.get_typed_func::<(i32,), i32>("count").unwrap();
+    assert!(out_of_fuel(count.call((1000,))));
+    assert!(out_of_fuel(count.call((1000,))));
+    instance.add_fuel(1_000_000).unwrap();
+    assert_eq!(count.call((1000,)).unwrap(), 1000);
+}
+
+#[test]
+fn fuel_remaining_decreases() {
+    let instance = fueled(1_000_000);
+    let count = instance.get_typed_func::<(i32,), i32>("count").unwrap();
+    let initial = instance.fuel_remaining().unwrap();
+    assert_eq!(initial, 1_000_000);
+    count.call((10,)).unwrap();
+    let after_few = instance.fuel_remaining().unwrap();
+    assert!(after_few < initial);
+    count.call((100,)).unwrap();
+    let after_more = instance.fuel_remaining().unwrap();
+    assert!(initial - after_few < after_few - after_more);
+}
+
+#[test]
+fn fuel_is_not_enabled_by_default() {
+    let instance = instantiate(LOOPS.as_bytes(), HashMap::new()).unwrap();
+    assert_eq!(instance.fuel_remaining(), None);
+    let error = instance.add_fuel(1).unwrap_err();
+    assert!(error.downcast::<FuelNotEnabled>().is_ok());
+}
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use wasmtime_embed::{
+    instantiate, Func, FuncType, HostModuleBuilder, ImportSet, InstanceToken, RuntimeValue, Trap,
+    TrapCode, ValType,
+};
+
+const CALLER: &str = r#"(module
+  (import "host" "f" (func $f (param i32) (result i32)))
+  (func (export "call") (param i32) (result i32)
+    local.get 0
+    call $f))"#;
+
+fn instantiate_caller(f: Func) -> InstanceToken {
+    let host = HostModuleBuilder::new().func("f", f).build();
+    let mut imports = HashMap::new();
+    imports.insert(String::from("host"), ImportSet::InstanceExports(host));
+    instantiate(CALLER.as_bytes(), imports).expect("instantiate")
+}
+
+fn call_trap(instance: &InstanceToken) -> Trap {
+    let call = instance.get_typed_func::<(i32,), i32>("call").expect("call");
+    call.call((1,))
+        .expect_err("trap")
+        .downcast::<Trap>()
+        .expect("Trap")
+}
+
+#[test]
+fn dynamic_func_returns_values() {
+    let ty = FuncType::new(vec![ValType::I32], vec![ValType::I32]);
+    let f = Func::new(ty, |args| match args[0] {
+        RuntimeValue::I32(i) => Ok(vec![RuntimeValue::I32(i * 2)]),
+        _ => unreachable!(),
+    });
+    let instance = instantiate_caller(f);
+    let call = instance.get_typed_func::<(i32,), i32>("call").unwrap();
+    assert_eq!(call.call((21,)).unwrap(), 42);
+}
+
+#[test]
+fn dynamic_func_error_traps() {
+    let ty = FuncType::new(vec![ValType::I32], vec![ValType::I32]);
+    let f = Func::new(ty, |_| Err(Trap::new("refused")));
+    let trap = call_trap(&instantiate_caller(f));
+    assert_eq!(trap.code(), TrapCode::User);
+    assert_eq!(trap.message(), "refused");
+}
+
+#[test]
+fn dynamic_func_panic_traps() {
+    let ty = FuncType::new(vec![ValType::I32], vec![ValType::I32]);
+    let f = Func::new(ty, |_| panic!("boom"));
+    let trap = call_trap(&instantiate_caller(f));
+    assert_eq!(trap.code(), TrapCode::User);
+    assert!(trap.message().contains("boom"), "{}", trap.message());
+}
+
+#[test]
+fn dynamic_func_wrong_result_type_traps() {
+    let ty = FuncType::new(vec![ValType::I32], vec![ValType::I32]);
+    let f = Func::new(ty, |_| Ok(vec![RuntimeValue::I64(1)]));
+    let trap = call_trap(&instantiate_caller(f));
+    assert!(trap.message().contains("wrong type"), "{}", trap.message());
+}
+
+#[test]
+fn wrapped_closure_is_called() {
+    let instance = instantiate_caller(Func::wrap(|a: i32| a + 1));
+    let call = instance.get_typed_func::<(i32,), i32>("call").unwrap();
+    assert_eq!(call.call((41,)).unwrap(), 42);
+}
+
+#[test]
+fn wrapped_closure_panic_traps() {
+    let f = Func::wrap(|a: i32| -> i32 {
+        if a > 0 {
+            panic!("positive argument");
+        }
+        a
+    });
+    let trap = call_trap(&instantiate_caller(f));
+    assert_eq!(trap.code(), TrapCode::User);
+    assert!(trap.message().contains("positive argument"), "{}", trap.message());
+}
+
+#[test]
+fn start_function_trap_is_reported_once() {
+    let starter = r#"(module
+      (import "host" "f" (func $f (param i32) (result i32)))
+      (func $start (drop (call $f (i32.const 0))))
+      (start $start))"#;
+    let ty = FuncType::new(vec![ValType::I32], vec![ValType::I32]);
+    let host = HostModuleBuilder::new()
+        .func("f", Func::new(ty, |_| Err(Trap::new("start refused"))))
+        .build();
+    let mut imports = HashMap::new();
+    imports.insert(String::from("host"), ImportSet::InstanceExports(host));
+    let error = instantiate(starter.as_bytes(), imports).unwrap_err();
+    let trap = error.downcast::<Trap>().expect("Trap");
+    assert_eq!(trap.message(), "start refused");
+
+    // The trap of the start function is not reported for the next trap.
+    let trapping = r#"(module (func (export "call") (param i32) (result i32) unreachable))"#;
+    let instance = instantiate(trapping.as_bytes(), HashMap::new()).unwrap();
+    let trap = call_trap(&instance);
+    assert_eq!(trap.code(), TrapCode::Unreachable);
+}
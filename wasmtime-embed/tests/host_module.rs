@@ -0,0 +1,140 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::ptr;
+use wasmtime_embed::{
+    instantiate, HostModuleBuilder, ImportSet, InstanceToken, MemoryType, RuntimeValue, TableType,
+    Trap, TrapCode,
+};
+use wasmtime_runtime::{Export, VMCallerCheckedAnyfunc};
+
+fn instantiate_with(wat: &str, host: &InstanceToken) -> InstanceToken {
+    let mut imports = HashMap::new();
+    imports.insert(String::from("host"), ImportSet::InstanceExports(host.clone()));
+    instantiate(wat.as_bytes(), imports).expect("instantiate")
+}
+
+const GLOBALS: &str = r#"(module
+  (import "host" "counter" (global $counter (mut i32)))
+  (import "host" "limit" (global $limit i64))
+  (func (export "get") (result i32) (global.get $counter))
+  (func (export "set") (param i32) (global.set $counter (local.get 0)))
+  (func (export "limit") (result i64) (global.get $limit)))"#;
+
+/// Returns the location of the i32 global `name` of `host`.
+fn host_global(host: &InstanceToken, name: &str) -> *mut i32 {
+    match host.handle().clone().lookup(name) {
+        Some(Export::Global { definition, .. }) => unsafe { (*definition).as_i32_mut() },
+        _ => panic!("no global {}", name),
+    }
+}
+
+#[test]
+fn globals_are_shared_with_wasm() {
+    let host = HostModuleBuilder::new()
+        .global("counter", RuntimeValue::I32(7), true)
+        .global("limit", RuntimeValue::I64(1 << 40), false)
+        .build();
+    let guest = instantiate_with(GLOBALS, &host);
+    let get = guest.get_typed_func::<(), i32>("get").unwrap();
+    let set = guest.get_typed_func::<(i32,), ()>("set").unwrap();
+    let limit = guest.get_typed_func::<(), i64>("limit").unwrap();
+    let counter = host_global(&host, "counter");
+
+    assert_eq!(get.call(()).unwrap(), 7);
+    assert_eq!(limit.call(()).unwrap(), 1 << 40);
+    set.call((9,)).unwrap();
+    assert_eq!(unsafe { *counter }, 9);
+    unsafe { *counter = 11 };
+    assert_eq!(get.call(()).unwrap(), 11);
+}
+
+const MEMORY: &str = r#"(module
+  (import "host" "memory" (memory 1))
+  (func (export "load") (param i32) (result i32) (i32.load8_u (local.get 0)))
+  (func (export "store") (param i32 i32) (i32.store8 (local.get 0) (local.get 1)))
+  (func (export "size") (result i32) (memory.size)))"#;
+
+#[test]
+fn memories_are_shared_with_wasm() {
+    let host = HostModuleBuilder::new()
+        .memory("memory", MemoryType::new(1, Some(2)))
+        .build();
+    let first = instantiate_with(MEMORY, &host);
+    let second = instantiate_with(MEMORY, &host);
+    let memory = host.get_export("memory").unwrap();
+    let memory: &mut [u8] = unsafe { memory.get_memory_slice_mut(0, 65536, 1).unwrap() };
+    let load = second.get_typed_func::<(i32,), i32>("load").unwrap();
+    let store = first.get_typed_func::<(i32, i32), ()>("store").unwrap();
+
+    assert_eq!(first.get_typed_func::<(), i32>("size").unwrap().call(()).unwrap(), 1);
+    memory[100] = 42;
+    assert_eq!(load.call((100,)).unwrap(), 42);
+    store.call((200, 7)).unwrap();
+    assert_eq!(memory[200], 7);
+    assert_eq!(load.call((200,)).unwrap(), 7);
+}
+
+// Fills the first element of the host table.
+const TABLE_FILLER: &str = r#"(module
+  (import "host" "table" (table 2 anyfunc))
+  (func $seven (result i32) (i32.const 7))
+  (elem (i32.const 0) $seven))"#;
+
+const TABLE_CALLER: &str = r#"(module
+  (import "host" "table" (table 2 anyfunc))
+  (type $t (func (result i32)))
+  (func (export "call") (param i32) (result i32) (call_indirect (type $t) (local.get 0))))"#;
+
+#[test]
+fn tables_are_shared_with_wasm() {
+    let host = HostModuleBuilder::new()
+        .table("table", TableType::new(2, None))
+        .build();
+    let _filler = instantiate_with(TABLE_FILLER, &host);
+    let caller = instantiate_with(TABLE_CALLER, &host);
+    let call = caller.get_typed_func::<(i32,), i32>("call").unwrap();
+
+    assert_eq!(call.call((0,)).unwrap(), 7);
+    let trap = call.call((1,)).unwrap_err().downcast::<Trap>().unwrap();
+    assert_eq!(trap.code(), TrapCode::IndirectCallToNull);
+
+    let definition = match host.handle().clone().lookup("table") {
+        Some(Export::Table { definition, .. }) => definition,
+        _ => panic!("no table"),
+    };
+    unsafe {
+        assert_eq!((*definition).current_elements, 2);
+        let elements = (*definition).base as *mut VMCallerCheckedAnyfunc;
+        assert!(!(*elements).func_ptr.is_null());
+        assert!((*elements.add(1)).func_ptr.is_null());
+        // The host sets the second element to the function of the first.
+        ptr::copy_nonoverlapping(elements, elements.add(1), 1);
+    }
+    assert_eq!(call.call((1,)).unwrap(), 7);
+}
+
+const COUNTER: &str = r#"(module
+  (import "host" "inc" (func $inc))
+  (import "host" "get" (func $get (result i32)))
+  (func (export "inc_twice") (result i32) (call $inc) (call $inc) (call $get)))"#;
+
+#[test]
+fn state_is_shared_by_functions() {
+    let mut builder = HostModuleBuilder::new();
+    let state = builder.state(Cell::new(0));
+    let inc = state.clone();
+    let get = state.clone();
+    let host = builder
+        .wrap("inc", move || inc.set(inc.get() + 1))
+        .wrap("get", move || -> i32 { get.get() })
+        .build();
+    let guest = instantiate_with(COUNTER, &host);
+    let inc_twice = guest.get_typed_func::<(), i32>("inc_twice").unwrap();
+
+    assert_eq!(inc_twice.call(()).unwrap(), 2);
+    let state = host.host_state::<Cell<i32>>().expect("state");
+    assert_eq!(state.get(), 2);
+    state.set(10);
+    assert_eq!(inc_twice.call(()).unwrap(), 12);
+    assert!(host.host_state::<Cell<u32>>().is_none());
+}
@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+use wasmtime_embed::{
+    instantiate, HostModuleBuilder, ImportSet, InstanceToken, LinkError, RuntimeValue,
+};
+
+fn instantiate_with(wat: &str, host: InstanceToken) -> Result<InstanceToken, LinkError> {
+    let mut imports = HashMap::new();
+    imports.insert(String::from("host"), ImportSet::InstanceExports(host));
+    instantiate(wat.as_bytes(), imports).map_err(|e| e.downcast::<LinkError>().expect("LinkError"))
+}
+
+fn global_host(value: RuntimeValue, mutable: bool) -> InstanceToken {
+    HostModuleBuilder::new().global("g", value, mutable).build()
+}
+
+#[test]
+fn global_type_and_mutability_must_match() {
+    let immutable = r#"(module (import "host" "g" (global i32)))"#;
+    assert!(instantiate_with(immutable, global_host(RuntimeValue::I32(1), false)).is_ok());
+
+    let error = instantiate_with(immutable, global_host(RuntimeValue::I32(1), true)).err();
+    let error = error.expect("mutability mismatch");
+    assert_eq!(error.0[0].expected, "global i32");
+    assert_eq!(error.0[0].provided.as_ref().map(String::as_str), Some("global (mut i32)"));
+
+    let error = instantiate_with(immutable, global_host(RuntimeValue::I64(1), false)).err();
+    assert!(error.is_some(), "type mismatch");
+
+    let mutable = r#"(module (import "host" "g" (global (mut i32))))"#;
+    assert!(instantiate_with(mutable, global_host(RuntimeValue::I32(1), true)).is_ok());
+    assert!(instantiate_with(mutable, global_host(RuntimeValue::I32(1), false)).is_err());
+}
+
+#[test]
+fn all_import_errors_are_reported() {
+    let wat = r#"(module
+      (import "host" "f" (func (param i32)))
+      (import "host" "missing" (func))
+      (import "host" "g" (global i32)))"#;
+    let host = HostModuleBuilder::new()
+        .wrap("f", |_: i64| ())
+        .wrap("missin", || ())
+        .build();
+    let error = instantiate_with(wat, host).err().expect("link error");
+    let fields: Vec<&str> = error.0.iter().map(|e| e.field.as_str()).collect();
+    assert_eq!(fields, vec!["f", "missing", "g"]);
+    assert_eq!(error.0[0].provided.as_ref().map(String::as_str), Some("func (i64) -> ()"));
+    assert_eq!(error.0[1].suggestions, vec!["missin"]);
+    assert_eq!(error.0[2].provided, None);
+}
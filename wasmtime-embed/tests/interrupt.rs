@@ -0,0 +1,44 @@
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+use wasmtime_embed::{instantiate_in_context, Config, ContextToken, InstanceToken, Trap, TrapCode};
+
+const SPIN: &str = r#"(module (func (export "spin") (loop (br 0))))"#;
+
+fn interruptable() -> InstanceToken {
+    let mut config = Config::new();
+    config.interruptable(true);
+    let context = ContextToken::with_config(config);
+    instantiate_in_context(SPIN.as_bytes(), HashMap::new(), context).unwrap()
+}
+
+fn spin(instance: &InstanceToken) -> Trap {
+    let spin = instance.get_typed_func::<(), ()>("spin").unwrap();
+    spin.call(()).unwrap_err().downcast::<Trap>().expect("Trap")
+}
+
+#[test]
+fn loop_is_interrupted_from_another_thread() {
+    let instance = interruptable();
+    let handle = instance.interrupt_handle().unwrap();
+    let interrupter = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        handle.interrupt();
+    });
+    assert_eq!(spin(&instance).code(), TrapCode::Interrupt);
+    interrupter.join().unwrap();
+}
+
+#[test]
+fn interrupt_before_call_is_honored() {
+    let instance = interruptable();
+    instance.interrupt_handle().unwrap().interrupt();
+    assert_eq!(spin(&instance).code(), TrapCode::Interrupt);
+}
+
+#[test]
+fn interrupt_handle_requires_config() {
+    let context = ContextToken::create();
+    let instance = instantiate_in_context(SPIN.as_bytes(), HashMap::new(), context).unwrap();
+    assert!(instance.interrupt_handle().is_none());
+}
@@ -0,0 +1,93 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use wasmtime_embed::{instantiate_in_context, ContextToken, ResourceLimitExceeded, ResourceLimiter};
+
+const GROW: &str = r#"(module
+  (memory 1 4)
+  (func (export "grow") (param i32) (result i32)
+    local.get 0
+    memory.grow))"#;
+
+struct Limits {
+    memory_pages: u32,
+    instances: usize,
+    memory_requests: Rc<Cell<usize>>,
+}
+
+impl Limits {
+    fn new(memory_pages: u32, instances: usize) -> Limits {
+        Limits {
+            memory_pages,
+            instances,
+            memory_requests: Rc::new(Cell::new(0)),
+        }
+    }
+}
+
+impl ResourceLimiter for Limits {
+    fn memory_growing(&self, _current: u32, desired: u32, _maximum: Option<u32>) -> bool {
+        self.memory_requests.set(self.memory_requests.get() + 1);
+        desired <= self.memory_pages
+    }
+
+    fn instances(&self) -> usize {
+        self.instances
+    }
+}
+
+fn limited_context(limits: Limits) -> ContextToken {
+    let context = ContextToken::create();
+    context.set_limiter(limits);
+    context
+}
+
+#[test]
+fn memory_grow_is_vetoed() {
+    let context = limited_context(Limits::new(2, 10));
+    let instance = instantiate_in_context(GROW.as_bytes(), HashMap::new(), context).unwrap();
+    let grow = instance.get_typed_func::<(i32,), i32>("grow").unwrap();
+    assert_eq!(grow.call((1,)).unwrap(), 1);
+    assert_eq!(grow.call((1,)).unwrap(), -1);
+}
+
+#[test]
+fn memory_beyond_maximum_is_not_asked_for() {
+    let limits = Limits::new(100, 10);
+    let requests = limits.memory_requests.clone();
+    let context = limited_context(limits);
+    let instance = instantiate_in_context(GROW.as_bytes(), HashMap::new(), context).unwrap();
+    let after_instantiation = requests.get();
+    let grow = instance.get_typed_func::<(i32,), i32>("grow").unwrap();
+    assert_eq!(grow.call((10,)).unwrap(), -1);
+    assert_eq!(requests.get(), after_instantiation);
+}
+
+#[test]
+fn initial_memory_is_vetoed() {
+    let context = limited_context(Limits::new(0, 10));
+    let error = instantiate_in_context(GROW.as_bytes(), HashMap::new(), context).unwrap_err();
+    assert!(error.downcast::<ResourceLimitExceeded>().is_ok());
+}
+
+#[test]
+fn instance_count_is_limited() {
+    let context = limited_context(Limits::new(10, 2));
+    for _ in 0..2 {
+        instantiate_in_context(GROW.as_bytes(), HashMap::new(), context.clone()).unwrap();
+    }
+    let error = instantiate_in_context(GROW.as_bytes(), HashMap::new(), context).unwrap_err();
+    assert!(error.downcast::<ResourceLimitExceeded>().is_ok());
+}
+
+#[test]
+fn failed_instantiations_are_not_counted() {
+    let context = limited_context(Limits::new(10, 1));
+    let trapping = r#"(module (func $start unreachable) (start $start))"#;
+    for _ in 0..3 {
+        assert!(
+            instantiate_in_context(trapping.as_bytes(), HashMap::new(), context.clone()).is_err()
+        );
+    }
+    instantiate_in_context(GROW.as_bytes(), HashMap::new(), context).unwrap();
+}
@@ -0,0 +1,63 @@
+use wasmtime_embed::{ContextToken, HostModuleBuilder, InstanceAlreadyDefined, Linker, Trap};
+
+const LIB: &str = r#"(module
+  (memory (export "memory") 1)
+  (global (export "base") i32 (i32.const 7))
+  (table (export "table") 1 anyfunc)
+  (func (export "load") (param i32) (result i32) (i32.load (local.get 0))))"#;
+
+const APP: &str = r#"(module
+  (import "lib" "memory" (memory 1))
+  (import "lib" "base" (global i32))
+  (import "lib" "table" (table 1 anyfunc))
+  (import "lib" "load" (func $load (param i32) (result i32)))
+  (import "lib" "missing" (func $missing))
+  (func (export "store_base") (i32.store (i32.const 0) (global.get 0)))
+  (func (export "load") (result i32) (call $load (i32.const 0)))
+  (func (export "call_missing") (call $missing)))"#;
+
+#[test]
+fn named_instances_resolve_imports() {
+    let mut linker = Linker::new(ContextToken::create());
+    let lib = linker.instantiate_named("lib", LIB.as_bytes()).unwrap();
+    let app = r#"(module
+      (import "lib" "load" (func $load (param i32) (result i32)))
+      (func (export "load") (result i32) (call $load (i32.const 0))))"#;
+    let app = linker.instantiate(app.as_bytes()).unwrap();
+    assert_eq!(app.get_typed_func::<(), i32>("load").unwrap().call(()).unwrap(), 0);
+    assert!(linker.get("lib").is_some());
+    drop(lib);
+
+    let error = linker.instance("lib", HostModuleBuilder::new().build()).err();
+    let error = error.expect("duplicate instance");
+    assert!(error.downcast_ref::<InstanceAlreadyDefined>().is_some());
+}
+
+#[test]
+fn missing_imports_fail_without_stubs() {
+    let mut linker = Linker::new(ContextToken::create());
+    linker.instantiate_named("lib", LIB.as_bytes()).unwrap();
+    assert!(linker.instantiate(APP.as_bytes()).is_err());
+}
+
+#[test]
+fn stubs_keep_other_exports() {
+    let mut linker = Linker::new(ContextToken::create());
+    let lib = linker.instantiate_named("lib", LIB.as_bytes()).unwrap();
+    linker.stub_unresolved_imports(true);
+    let app = linker.instantiate(APP.as_bytes()).unwrap();
+
+    // The memory and the global are the ones of "lib".
+    app.get_typed_func::<(), ()>("store_base").unwrap().call(()).unwrap();
+    let load = lib.get_typed_func::<(i32,), i32>("load").unwrap();
+    assert_eq!(load.call((0,)).unwrap(), 7);
+    assert_eq!(app.get_typed_func::<(), i32>("load").unwrap().call(()).unwrap(), 7);
+
+    let error = app
+        .get_typed_func::<(), ()>("call_missing")
+        .unwrap()
+        .call(())
+        .unwrap_err();
+    let trap = error.downcast::<Trap>().expect("Trap");
+    assert_eq!(trap.message(), "unimplemented import lib.missing");
+}
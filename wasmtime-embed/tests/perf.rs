@@ -0,0 +1,45 @@
+use std::collections::HashMap;
+use std::fs;
+use std::process;
+use wasmtime_embed::{instantiate_in_context, Config, ContextToken};
+
+// Returns the perf map entries added by instantiating `wat`.
+fn perf_map_entries(wat: &str) -> Vec<(usize, usize, String)> {
+    let path = format!("/tmp/perf-{}.map", process::id());
+    let before = fs::read_to_string(&path).unwrap_or_default().len();
+    let mut config = Config::new();
+    config.perf_map(true);
+    let context = ContextToken::with_config(config);
+    instantiate_in_context(wat.as_bytes(), HashMap::new(), context).unwrap();
+    fs::read_to_string(&path).unwrap()[before..]
+        .lines()
+        .map(|line| {
+            let mut fields = line.splitn(3, ' ');
+            let address = usize::from_str_radix(fields.next().unwrap(), 16).unwrap();
+            let size = usize::from_str_radix(fields.next().unwrap(), 16).unwrap();
+            (address, size, fields.next().unwrap().to_owned())
+        })
+        .collect()
+}
+
+#[test]
+fn perf_map_lists_every_defined_function() {
+    let single = r#"(module (func (export "f") (result i32) (i32.const 1)))"#;
+    let entries = perf_map_entries(single);
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].2, "wasm::func[0]");
+    assert!(entries[0].1 > 0);
+
+    let several = r#"(module
+      (func (export "a") (result i32) (i32.const 1))
+      (func (export "b") (param i32) (result i32) (i32.add (local.get 0) (i32.const 2)))
+      (func (export "c")))"#;
+    let entries = perf_map_entries(several);
+    let names = entries.iter().map(|(_, _, name)| name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, vec!["wasm::func[0]", "wasm::func[1]", "wasm::func[2]"]);
+    assert!(entries.iter().all(|&(_, size, _)| size > 0));
+    for pair in entries.windows(2) {
+        let ((address, size, _), (next, _, _)) = (&pair[0], &pair[1]);
+        assert!(address + size <= *next);
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use wasmtime_embed::{
+    live_counts, run_command, with_store_data, Config, DirRights, HostModuleBuilder, ImportSet,
+    ScratchFs, Store, WasiConfig,
+};
+
+const LOGGER: &str = r#"(module
+  (import "env" "log" (func $log (param i32)))
+  (func (export "run") (call $log (i32.const 1)) (call $log (i32.const 2))))"#;
+
+fn instantiate_logger(store: &mut Store<Vec<i32>>) {
+    let log = store.add(
+        HostModuleBuilder::new()
+            .wrap_with_data("log", |log: &mut Vec<i32>, n: i32| log.push(n))
+            .build(),
+    );
+    let mut imports = HashMap::new();
+    imports.insert(String::from("env"), ImportSet::InstanceExports(log));
+    let instance = store.instantiate(LOGGER.as_bytes(), imports).unwrap();
+    instance.get_typed_func::<(), ()>("run").unwrap().call(()).unwrap();
+}
+
+#[test]
+fn host_functions_receive_store_data() {
+    let mut store = Store::with_data(Vec::new());
+    instantiate_logger(&mut store);
+    assert_eq!(*store.data(), vec![1, 2]);
+    assert!(with_store_data(|_: &mut Vec<i32>| ()).is_err());
+}
+
+#[test]
+fn clear_releases_instances_and_code() {
+    let before = live_counts();
+    let mut store = Store::with_data(Vec::new());
+    instantiate_logger(&mut store);
+    let used = live_counts();
+    assert!(used.instances > before.instances);
+    assert!(used.code_bytes > before.code_bytes);
+
+    store.clear();
+    let cleared = live_counts();
+    assert_eq!(cleared.instances, before.instances);
+    assert_eq!(cleared.code_bytes, before.code_bytes);
+    // Only the new empty context of the store is left.
+    assert_eq!(cleared.contexts, before.contexts + 1);
+
+    instantiate_logger(&mut store);
+    drop(store);
+    assert_eq!(live_counts(), before);
+}
+
+#[test]
+fn wasi_with_restricted_preopen_is_released() {
+    let fs = ScratchFs::new().unwrap();
+    let before = live_counts();
+    let mut wasi = WasiConfig::new();
+    wasi.preopen_scratch_with_rights("/", &fs, DirRights::read_only());
+    let command = r#"(module (func (export "_start")))"#;
+    assert!(run_command(command.as_bytes(), &mut wasi).unwrap().success());
+    assert_eq!(live_counts(), before);
+}
+
+#[test]
+fn one_function_module_code_is_counted() {
+    // The counts are per thread, and every test runs in a thread of its own.
+    assert_eq!(live_counts().code_bytes, 0);
+    let mut store = Store::new();
+    let single = r#"(module (func (export "f") (result i32) (i32.const 1)))"#;
+    store.instantiate(single.as_bytes(), HashMap::new()).unwrap();
+    assert!(live_counts().code_bytes > 0);
+    drop(store);
+    assert_eq!(live_counts().code_bytes, 0);
+}
+
+#[test]
+fn store_with_data_uses_config() {
+    let mut config = Config::new();
+    config.consume_fuel(true).initial_fuel(1_000_000);
+    let mut store = Store::with_data_and_config(Vec::new(), config);
+    instantiate_logger(&mut store);
+    assert_eq!(*store.data(), vec![1, 2]);
+    let instance = store.instances().last().unwrap();
+    assert!(instance.fuel_remaining().unwrap() < 1_000_000);
+}
@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use wasmparser::{ModuleReader, SectionCode};
+use wasmtime_embed::{
+    instantiate, instantiate_in_context, wat2wasm, Config, ContextToken, InstanceToken, Trap,
+    TrapCode,
+};
+
+const TRAPS: &str = r#"(module
+  (type $i32_to_i32 (func (param i32) (result i32)))
+  (memory 1)
+  (table 1 anyfunc)
+  (elem (i32.const 0) $nop)
+  (func $nop)
+  (func (export "unreachable") (param i32) (result i32) nop unreachable)
+  (func (export "div") (param i32) (result i32)
+    (i32.div_s (i32.const 7) (local.get 0)))
+  (func (export "load") (param i32) (result i32)
+    (i32.load (local.get 0)))
+  (func (export "indirect") (param i32) (result i32)
+    (call_indirect (type $i32_to_i32) (local.get 0) (i32.const 0)))
+  (func $recurse (export "recurse") (param i32) (result i32)
+    (call $recurse (i32.add (local.get 0) (i32.const 1)))))"#;
+
+struct Traps {
+    binary: Vec<u8>,
+    instance: InstanceToken,
+}
+
+impl Traps {
+    fn new() -> Traps {
+        let binary = wat2wasm(TRAPS).unwrap();
+        let instance = instantiate(&binary, HashMap::new()).unwrap();
+        Traps { binary, instance }
+    }
+
+    fn call(&self, name: &str, arg: i32) -> Trap {
+        let func = self.instance.get_typed_func::<(i32,), i32>(name).unwrap();
+        func.call((arg,)).unwrap_err().downcast::<Trap>().expect("Trap")
+    }
+
+    /// Returns the module offset of the instruction at `skip` bytes into
+    /// `pattern` in the body of defined function `func`.
+    fn offset(&self, func: usize, pattern: &[u8], skip: usize) -> usize {
+        let mut reader = ModuleReader::new(&self.binary).unwrap();
+        while !reader.eof() {
+            let section = reader.read().unwrap();
+            if let SectionCode::Code = section.code {
+                let body = section.get_code_section_reader().unwrap().into_iter().nth(func);
+                let reader = body.unwrap().unwrap().get_binary_reader();
+                let start = reader.original_position();
+                let code = &self.binary[start..start + reader.bytes_remaining()];
+                let found = code.windows(pattern.len()).position(|w| w == pattern);
+                return start + found.expect("pattern") + skip;
+            }
+        }
+        panic!("no code section");
+    }
+}
+
+#[test]
+fn unreachable_trap() {
+    let traps = Traps::new();
+    let trap = traps.call("unreachable", 0);
+    assert_eq!(trap.code(), TrapCode::Unreachable);
+    assert_eq!(trap.func_index(), Some(1));
+    // nop, unreachable
+    assert_eq!(trap.module_offset(), Some(traps.offset(1, &[0x01, 0x00], 1)));
+}
+
+#[test]
+fn integer_division_by_zero_trap() {
+    let traps = Traps::new();
+    let trap = traps.call("div", 0);
+    assert_eq!(trap.code(), TrapCode::IntegerDivisionByZero);
+    assert_eq!(trap.func_index(), Some(2));
+    // i32.const 7, local.get 0, i32.div_s
+    let div = traps.offset(2, &[0x41, 0x07, 0x20, 0x00, 0x6d], 4);
+    assert_eq!(trap.module_offset(), Some(div));
+}
+
+#[test]
+fn memory_out_of_bounds_trap() {
+    let traps = Traps::new();
+    let trap = traps.call("load", 0x10000);
+    assert_eq!(trap.code(), TrapCode::MemoryOutOfBounds);
+    assert_eq!(trap.func_index(), Some(3));
+    // local.get 0, i32.load
+    assert_eq!(trap.module_offset(), Some(traps.offset(3, &[0x20, 0x00, 0x28], 2)));
+}
+
+#[test]
+fn indirect_call_type_mismatch_trap() {
+    let traps = Traps::new();
+    let trap = traps.call("indirect", 0);
+    assert_eq!(trap.code(), TrapCode::BadSignature);
+    assert_eq!(trap.func_index(), Some(4));
+    // i32.const 0, call_indirect
+    assert_eq!(trap.module_offset(), Some(traps.offset(4, &[0x41, 0x00, 0x11], 2)));
+}
+
+#[test]
+fn stack_overflow_trap() {
+    let traps = Traps::new();
+    let trap = traps.call("recurse", 0);
+    assert_eq!(trap.code(), TrapCode::StackOverflow);
+    // The guard page is hit outside of a known trap site, so the faulting
+    // instruction is not known, only the function the host called.
+    assert_eq!(trap.module_offset(), None);
+    assert_eq!(trap.func_index(), None);
+    assert_eq!(trap.frames()[0].func_index(), 5);
+}
+
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn trap_is_send_and_sync() {
+    assert_send_sync::<Trap>();
+}
+
+fn leb(mut value: usize, out: &mut Vec<u8>) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn custom_section(name: &str, contents: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::new();
+    leb(name.len(), &mut payload);
+    payload.extend(name.as_bytes());
+    payload.extend(contents);
+    let mut section = vec![0];
+    leb(payload.len(), &mut section);
+    section.extend(payload);
+    section
+}
+
+// Prefixes a DWARF unit with its 32-bit length.
+fn dwarf_unit(body: Vec<u8>) -> Vec<u8> {
+    let mut unit = (body.len() as u32).to_le_bytes().to_vec();
+    unit.extend(body);
+    unit
+}
+
+/// Returns `(module (func $ok nop) (func $fail (export "fail") nop unreachable))`
+/// with a "name" section and the DWARF of a compilation unit "fixture.c",
+/// in which the `nop` of `$fail` is on line 10 and `unreachable` on line 12.
+fn debug_fixture() -> Vec<u8> {
+    let mut binary =
+        wat2wasm(r#"(module (func nop) (func (export "fail") nop unreachable))"#).unwrap();
+
+    // Addresses in wasm DWARF are relative to the code section payload:
+    // count, body of $ok (size, locals, nop, end) and of $fail.
+    const NOP: u32 = 7;
+    const UNREACHABLE: u32 = 8;
+    const CODE_END: u32 = 10;
+    let mut reader = ModuleReader::new(&binary).unwrap();
+    let mut code_start = None;
+    while !reader.eof() {
+        let section = reader.read().unwrap();
+        if let SectionCode::Code = section.code {
+            code_start = Some(section.range().start);
+        }
+    }
+    let code_start = code_start.expect("code section");
+    let code = &binary[code_start..code_start + CODE_END as usize];
+    assert_eq!(&code[NOP as usize..], &[0x01, 0x00, 0x0b]);
+
+    let mut functions = vec![2];
+    for (index, name) in [(0, "ok"), (1, "fail")].iter() {
+        functions.push(*index);
+        functions.push(name.len() as u8);
+        functions.extend(name.as_bytes());
+    }
+    let mut names = vec![1];
+    leb(functions.len(), &mut names);
+    names.extend(functions);
+
+    // DW_TAG_compile_unit without children: producer, language, name and
+    // comp_dir as inline strings, stmt_list, low_pc and high_pc.
+    let abbrev = vec![
+        1, 0x11, 0, 0x25, 0x08, 0x13, 0x05, 0x03, 0x08, 0x10, 0x17, 0x1b, 0x08, 0x11, 0x01,
+        0x12, 0x06, 0, 0, 0,
+    ];
+
+    let mut info = vec![4, 0, 0, 0, 0, 0, 4, 1];
+    info.extend(b"fixture\0");
+    info.extend(&[0x0c, 0x00]);
+    info.extend(b"fixture.c\0");
+    info.extend(&0u32.to_le_bytes());
+    info.extend(b"/\0");
+    info.extend(&0u32.to_le_bytes());
+    info.extend(&CODE_END.to_le_bytes());
+
+    // Minimum instruction length, maximum operations per instruction,
+    // default is_stmt, line base, line range, opcode base, standard opcode
+    // lengths, no include directories and file "fixture.c".
+    let mut header = vec![1, 1, 1, 0xfb, 14, 13, 0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1, 0];
+    header.extend(b"fixture.c\0");
+    header.extend(&[0, 0, 0, 0]);
+    let mut program = Vec::new();
+    for &(address, advance_line) in [(NOP, 9), (UNREACHABLE, 2)].iter() {
+        // DW_LNE_set_address, DW_LNS_advance_line, DW_LNS_copy
+        program.extend(&[0, 5, 2]);
+        program.extend(&address.to_le_bytes());
+        program.extend(&[3, advance_line, 1]);
+    }
+    program.extend(&[0, 5, 2]);
+    program.extend(&CODE_END.to_le_bytes());
+    // DW_LNE_end_sequence
+    program.extend(&[0, 1, 1]);
+    let mut line = vec![4, 0];
+    line.extend(&(header.len() as u32).to_le_bytes());
+    line.extend(header);
+    line.extend(program);
+
+    binary.extend(custom_section("name", &names));
+    binary.extend(custom_section(".debug_abbrev", &abbrev));
+    binary.extend(custom_section(".debug_info", &dwarf_unit(info)));
+    binary.extend(custom_section(".debug_line", &dwarf_unit(line)));
+    binary
+}
+
+#[test]
+fn frames_resolve_names_and_source_lines() {
+    let mut config = Config::new();
+    config.debug_info(true);
+    let context = ContextToken::with_config(config);
+    let instance = instantiate_in_context(&debug_fixture(), HashMap::new(), context).unwrap();
+    let fail = instance.get_typed_func::<(), ()>("fail").unwrap();
+    let trap = fail.call(()).unwrap_err().downcast::<Trap>().expect("Trap");
+    assert_eq!(trap.code(), TrapCode::Unreachable);
+    let frame = &trap.frames()[0];
+    assert_eq!(frame.func_index(), 1);
+    assert_eq!(frame.func_name(), Some("fail"));
+    assert_eq!(frame.source_location(), Some(("fixture.c", 12)));
+}
+
+#[test]
+fn frames_resolve_names_without_debug_info() {
+    let instance = instantiate(&debug_fixture(), HashMap::new()).unwrap();
+    let fail = instance.get_typed_func::<(), ()>("fail").unwrap();
+    let trap = fail.call(()).unwrap_err().downcast::<Trap>().expect("Trap");
+    let frame = &trap.frames()[0];
+    assert_eq!(frame.func_name(), Some("fail"));
+    assert_eq!(frame.source_location(), None);
+}
@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+use wasmtime_embed::{
+    instantiate_in_context, validate, wat2wasm, Config, ContextToken, UnsupportedFeature,
+};
+
+// A shared memory of the threads proposal.
+const SHARED_MEMORY: &[u8] = b"\0asm\x01\0\0\0\x05\x04\x01\x03\x01\x01";
+
+fn threads() -> Config {
+    let mut config = Config::new();
+    config.wasm_threads(true);
+    config
+}
+
+#[test]
+fn error_location() {
+    let binary = wat2wasm("(module (func) (func (result i32) i32.const 0))").unwrap();
+    assert!(validate(&binary, &Config::new()).is_ok());
+
+    // Drops the result of the second function, `i32.const 0` becoming `nop`.
+    let position = binary.windows(2).position(|w| w == [0x41, 0x00]).unwrap();
+    let mut broken = binary.clone();
+    broken[position] = 0x01;
+    broken[position + 1] = 0x01;
+    let error = validate(&broken, &Config::new()).unwrap_err();
+    assert_eq!(error.section.as_ref().map(String::as_str), Some("code"));
+    assert_eq!(error.func_index, Some(1));
+}
+
+#[test]
+fn proposal_is_validated_when_enabled() {
+    assert!(validate(SHARED_MEMORY, &Config::new()).is_err());
+    assert!(validate(SHARED_MEMORY, &threads()).is_ok());
+}
+
+#[test]
+fn unsupported_proposal_fails_to_instantiate() {
+    let context = ContextToken::with_config(threads());
+    let error = instantiate_in_context(SHARED_MEMORY, HashMap::new(), context)
+        .err()
+        .expect("error");
+    assert!(error.downcast_ref::<UnsupportedFeature>().is_some());
+}
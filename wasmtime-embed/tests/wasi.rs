@@ -0,0 +1,147 @@
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use wasmtime_embed::{
+    instantiate_reactor, run_command, ContextToken, ScratchFs, VirtualClock, WasiConfig, WasiError,
+};
+
+// Writes "hello\n" to stdout.
+const HELLO: &str = r#"(module
+  (import "wasi_unstable" "fd_write"
+    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 16) "hello\n")
+  (func (export "_start")
+    ;; iovec { buf: 16, len: 6 } at 0, written count at 8
+    (i32.store (i32.const 0) (i32.const 16))
+    (i32.store (i32.const 4) (i32.const 6))
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))"#;
+
+// Creates symbolic link "link" to "/etc/passwd" in the first preopen and
+// exits with the errno.
+const SYMLINK: &str = r#"(module
+  (import "wasi_unstable" "path_symlink"
+    (func $path_symlink (param i32 i32 i32 i32 i32) (result i32)))
+  (import "wasi_unstable" "proc_exit" (func $proc_exit (param i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 0) "/etc/passwd")
+  (data (i32.const 16) "link")
+  (func (export "_start")
+    (call $proc_exit
+      (call $path_symlink
+        (i32.const 0) (i32.const 11) (i32.const 3) (i32.const 16) (i32.const 4)))))"#;
+
+// Reads the realtime clock and 8 random bytes, returning them or -1 on
+// failure.
+const CLOCK_RANDOM: &str = r#"(module
+  (import "wasi_unstable" "clock_time_get"
+    (func $clock_time_get (param i32 i64 i32) (result i32)))
+  (import "wasi_unstable" "random_get" (func $random_get (param i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (func (export "now") (result i64)
+    (if (result i64) (call $clock_time_get (i32.const 0) (i64.const 1) (i32.const 0))
+      (then (i64.const -1))
+      (else (i64.load (i32.const 0)))))
+  (func (export "random") (result i64)
+    (if (result i64) (call $random_get (i32.const 8) (i32.const 8))
+      (then (i64.const -1))
+      (else (i64.load (i32.const 8)))))
+  (func (export "random_out_of_bounds") (result i32)
+    (call $random_get (i32.const 65530) (i32.const 8))))"#;
+
+#[derive(Clone, Default)]
+struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn captured_stdout() {
+    let mut wasi = WasiConfig::new();
+    let stdout = wasi.capture_stdout().unwrap();
+    let status = run_command(HELLO.as_bytes(), &mut wasi).unwrap();
+    assert!(status.success());
+    assert_eq!(stdout.contents().unwrap(), b"hello\n");
+}
+
+#[test]
+fn writer_receives_all_output() {
+    for _ in 0..20 {
+        let writer = SharedWriter::default();
+        let mut wasi = WasiConfig::new();
+        wasi.stdout_writer(writer.clone());
+        run_command(HELLO.as_bytes(), &mut wasi).unwrap();
+        assert_eq!(&writer.0.lock().unwrap()[..], b"hello\n");
+    }
+}
+
+#[test]
+fn consumed_stdio_is_not_reused() {
+    let mut wasi = WasiConfig::new();
+    wasi.stdout_writer(SharedWriter::default());
+    let context = ContextToken::create();
+    wasi.build(&context).unwrap();
+    match wasi.build(&context) {
+        Err(WasiError::StdioConsumed) => (),
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("stdio reused"),
+    }
+}
+
+#[test]
+fn guest_symlink_does_not_escape_scratch_fs() {
+    let fs = ScratchFs::new().unwrap();
+    fs.write_file("file", "contents").unwrap();
+    let mut wasi = WasiConfig::new();
+    wasi.preopen_scratch("/", &fs);
+    let status = run_command(SYMLINK.as_bytes(), &mut wasi).unwrap();
+    assert_eq!(status.code(), Some(0));
+
+    assert_eq!(fs.read_dir("/").unwrap(), vec!["file", "link"]);
+    assert_eq!(fs.read_file("file").unwrap(), b"contents");
+    assert!(fs.read_file("link").is_err());
+    assert!(fs.write_file("link", "overwritten").is_err());
+    assert!(fs.metadata("link").is_err());
+    assert!(!fs.exists("link"));
+}
+
+#[test]
+fn virtual_clock() {
+    let mut wasi = WasiConfig::new();
+    wasi.clock(VirtualClock::new(1000, 10));
+    let instance = instantiate_reactor(CLOCK_RANDOM.as_bytes(), &mut wasi).unwrap();
+    let now = instance.get_typed_func::<(), i64>("now").unwrap();
+    assert_eq!(now.call(()).unwrap(), 1000);
+    assert_eq!(now.call(()).unwrap(), 1010);
+}
+
+#[test]
+fn seeded_random() {
+    let random = |seed| {
+        let mut wasi = WasiConfig::new();
+        wasi.random_seed(seed);
+        let instance = instantiate_reactor(CLOCK_RANDOM.as_bytes(), &mut wasi).unwrap();
+        let random = instance.get_typed_func::<(), i64>("random").unwrap();
+        (random.call(()).unwrap(), random.call(()).unwrap())
+    };
+    let (first, second) = random(7);
+    assert_ne!(first, second);
+    assert_eq!(random(7), (first, second));
+    assert_ne!(random(8), (first, second));
+}
+
+#[test]
+fn random_out_of_bounds_faults() {
+    const EFAULT: i32 = 21;
+    let mut wasi = WasiConfig::new();
+    wasi.random_seed(0);
+    let instance = instantiate_reactor(CLOCK_RANDOM.as_bytes(), &mut wasi).unwrap();
+    let random = instance.get_typed_func::<(), i32>("random_out_of_bounds").unwrap();
+    assert_eq!(random.call(()).unwrap(), EFAULT);
+}
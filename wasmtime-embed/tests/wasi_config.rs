@@ -0,0 +1,155 @@
+use std::env;
+use std::fs;
+use tempfile::TempDir;
+use wasmtime_embed::{
+    instantiate_reactor, ContextToken, DirRights, InstanceToken, WasiConfig, WasiError,
+};
+
+const ENOTCAPABLE: i32 = 76;
+
+const GUEST: &str = r#"(module
+  (import "wasi_unstable" "path_open"
+    (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+  (import "wasi_unstable" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_unstable" "environ_sizes_get"
+    (func $environ_sizes_get (param i32 i32) (result i32)))
+  (import "wasi_unstable" "environ_get" (func $environ_get (param i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 100) "file")
+  (data (i32.const 200) "written")
+  ;; Writes `len` bytes at `buf` to descriptor `fd`.
+  (func $write (param $fd i32) (param $buf i32) (param $len i32)
+    (i32.store (i32.const 16) (local.get $buf))
+    (i32.store (i32.const 20) (local.get $len))
+    (drop (call $fd_write (local.get $fd) (i32.const 16) (i32.const 1) (i32.const 24))))
+  ;; Writes "written" to descriptor `fd`.
+  (func (export "greet") (param $fd i32)
+    (call $write (local.get $fd) (i32.const 200) (i32.const 7)))
+  ;; Prints the environment, "KEY=value" strings each ending with NUL.
+  (func (export "print_environ")
+    (drop (call $environ_sizes_get (i32.const 0) (i32.const 4)))
+    (drop (call $environ_get (i32.const 1024) (i32.const 2048)))
+    (call $write (i32.const 1) (i32.const 2048) (i32.load (i32.const 4))))
+  ;; Opens "file" in preopen `fd`, stores the descriptor at 0.
+  (func $open (param $fd i32) (param $oflags i32) (param $rights i64) (result i32)
+    (call $path_open (local.get $fd) (i32.const 0) (i32.const 100) (i32.const 4)
+      (local.get $oflags) (local.get $rights) (local.get $rights) (i32.const 0) (i32.const 0)))
+  ;; Prints "file" of preopen `fd`, returns the errno of opening it.
+  (func (export "print_file") (param $fd i32) (result i32) (local $errno i32)
+    ;; no oflags, fd_read right
+    (local.set $errno (call $open (local.get $fd) (i32.const 0) (i64.const 2)))
+    (if (i32.eqz (local.get $errno))
+      (then
+        (i32.store (i32.const 32) (i32.const 4096))
+        (i32.store (i32.const 36) (i32.const 1024))
+        (drop (call $fd_read (i32.load (i32.const 0)) (i32.const 32) (i32.const 1)
+          (i32.const 40)))
+        (call $write (i32.const 1) (i32.const 4096) (i32.load (i32.const 40)))))
+    (local.get $errno))
+  ;; Writes "written" to "file" of preopen `fd`, returns the errno of opening it.
+  (func (export "write_file") (param $fd i32) (result i32) (local $errno i32)
+    ;; creat | trunc, fd_write right
+    (local.set $errno (call $open (local.get $fd) (i32.const 9) (i64.const 64)))
+    (if (i32.eqz (local.get $errno))
+      (then (call $write (i32.load (i32.const 0)) (i32.const 200) (i32.const 7))))
+    (local.get $errno)))"#;
+
+fn call(instance: &InstanceToken, name: &str, fd: i32) -> i32 {
+    let f = instance.get_typed_func::<(i32,), i32>(name).unwrap();
+    f.call((fd,)).unwrap()
+}
+
+/// Returns host directory containing "file".
+fn host_dir() -> TempDir {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("file"), "host contents").unwrap();
+    dir
+}
+
+#[test]
+fn inherit_env_filtered() {
+    env::set_var("WASI_CONFIG_TEST_PASSED", "inherited");
+    env::set_var("WASI_CONFIG_TEST_FILTERED", "inherited");
+    env::set_var("WASI_CONFIG_TEST_OVERRIDDEN", "inherited");
+    let mut wasi = WasiConfig::new();
+    let stdout = wasi.capture_stdout().unwrap();
+    wasi.inherit_env_filtered(|name| {
+        name.starts_with("WASI_CONFIG_TEST_") && name != "WASI_CONFIG_TEST_FILTERED"
+    })
+    .env("WASI_CONFIG_TEST_OVERRIDDEN", "set");
+    let instance = instantiate_reactor(GUEST.as_bytes(), &mut wasi).unwrap();
+    let print_environ = instance.get_typed_func::<(), ()>("print_environ").unwrap();
+    print_environ.call(()).unwrap();
+
+    let output = stdout.contents().unwrap();
+    let mut environ = output
+        .split(|&b| b == 0)
+        .filter(|var| !var.is_empty())
+        .map(|var| String::from_utf8(var.to_vec()).unwrap())
+        .collect::<Vec<_>>();
+    environ.sort();
+    assert_eq!(
+        environ,
+        vec![
+            "WASI_CONFIG_TEST_OVERRIDDEN=set",
+            "WASI_CONFIG_TEST_PASSED=inherited",
+        ]
+    );
+}
+
+#[test]
+fn map_dir() {
+    let dir = host_dir();
+    let mut wasi = WasiConfig::new();
+    let stdout = wasi.capture_stdout().unwrap();
+    wasi.map_dir("/data", dir.path());
+    let instance = instantiate_reactor(GUEST.as_bytes(), &mut wasi).unwrap();
+    assert_eq!(call(&instance, "print_file", 3), 0);
+    assert_eq!(stdout.contents().unwrap(), b"host contents");
+    assert_eq!(call(&instance, "write_file", 3), 0);
+    assert_eq!(fs::read(dir.path().join("file")).unwrap(), b"written");
+}
+
+#[test]
+fn map_dir_with_rights() {
+    let dir = host_dir();
+    let mut wasi = WasiConfig::new();
+    let stdout = wasi.capture_stdout().unwrap();
+    wasi.map_dir_with_rights("/data", dir.path(), DirRights::read_only());
+    let instance = instantiate_reactor(GUEST.as_bytes(), &mut wasi).unwrap();
+    assert_eq!(call(&instance, "print_file", 3), 0);
+    assert_eq!(stdout.contents().unwrap(), b"host contents");
+    assert_eq!(call(&instance, "write_file", 3), ENOTCAPABLE);
+    assert_eq!(fs::read(dir.path().join("file")).unwrap(), b"host contents");
+}
+
+#[test]
+fn stdout_and_stderr_are_captured_apart() {
+    let mut wasi = WasiConfig::new();
+    let stdout = wasi.capture_stdout().unwrap();
+    let stderr = wasi.capture_stderr().unwrap();
+    let instance = instantiate_reactor(GUEST.as_bytes(), &mut wasi).unwrap();
+    let greet = instance.get_typed_func::<(i32,), ()>("greet").unwrap();
+    greet.call((1,)).unwrap();
+    greet.call((2,)).unwrap();
+    greet.call((2,)).unwrap();
+    assert_eq!(stdout.contents().unwrap(), b"written");
+    assert_eq!(stderr.contents().unwrap(), b"writtenwritten");
+}
+
+#[test]
+fn failed_build_keeps_stdio() {
+    let mut wasi = WasiConfig::new();
+    wasi.capture_stdout().unwrap();
+    // wasi-common rejects arguments containing NUL.
+    wasi.arg("bad\0arg");
+    let context = ContextToken::create();
+    for _ in 0..2 {
+        match wasi.build(&context) {
+            Err(WasiError::InstantiationFailed(_)) => (),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("argument with NUL accepted"),
+        }
+    }
+}
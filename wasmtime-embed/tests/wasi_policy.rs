@@ -0,0 +1,303 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use wasmtime_embed::{
+    instantiate_reactor, DenialReason, DirRights, InstanceToken, ScratchFs, VirtualClock,
+    WasiCategory, WasiConfig,
+};
+
+const ESUCCESS: i32 = 0;
+const EBADF: i32 = 8;
+const ENOTCAPABLE: i32 = 76;
+
+const OFLAGS_CREAT: i32 = 1 << 0;
+const OFLAGS_DIRECTORY: i32 = 1 << 1;
+const OFLAGS_TRUNC: i32 = 1 << 3;
+
+const RIGHT_FD_READ: i64 = 1 << 1;
+const RIGHT_FD_WRITE: i64 = 1 << 6;
+const RIGHT_PATH_CREATE_DIRECTORY: i64 = 1 << 9;
+const RIGHT_PATH_OPEN: i64 = 1 << 13;
+const RIGHTS_DIRECTORY: i64 = RIGHT_PATH_CREATE_DIRECTORY | RIGHT_PATH_OPEN;
+
+// Preopens in the order `guest` maps them.
+const RO_FD: i32 = 3;
+const RW_FD: i32 = 4;
+
+// Path strings, see the data segments of `GUEST`.
+const FILE: (i32, i32) = (100, 4);
+const DIR: (i32, i32) = (200, 3);
+const NEW: (i32, i32) = (300, 3);
+
+// Thin wrappers of the WASI functions, returning the errno.
+const GUEST: &str = r#"(module
+  (import "wasi_unstable" "path_open"
+    (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+  (import "wasi_unstable" "path_create_directory"
+    (func $path_create_directory (param i32 i32 i32) (result i32)))
+  (import "wasi_unstable" "path_unlink_file"
+    (func $path_unlink_file (param i32 i32 i32) (result i32)))
+  (import "wasi_unstable" "path_remove_directory"
+    (func $path_remove_directory (param i32 i32 i32) (result i32)))
+  (import "wasi_unstable" "path_rename"
+    (func $path_rename (param i32 i32 i32 i32 i32 i32) (result i32)))
+  (import "wasi_unstable" "fd_renumber" (func $fd_renumber (param i32 i32) (result i32)))
+  (import "wasi_unstable" "fd_close" (func $fd_close (param i32) (result i32)))
+  (import "wasi_unstable" "fd_prestat_get" (func $fd_prestat_get (param i32 i32) (result i32)))
+  (import "wasi_unstable" "clock_time_get"
+    (func $clock_time_get (param i32 i64 i32) (result i32)))
+  (import "wasi_unstable" "random_get" (func $random_get (param i32 i32) (result i32)))
+  (import "wasi_unstable" "environ_sizes_get"
+    (func $environ_sizes_get (param i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 100) "file")
+  (data (i32.const 200) "dir")
+  (data (i32.const 300) "new")
+  ;; The opened descriptor is stored at 0.
+  (func (export "path_open")
+    (param $fd i32) (param $path i32) (param $len i32) (param $oflags i32) (param $rights i64)
+    (result i32)
+    (call $path_open (local.get $fd) (i32.const 0) (local.get $path) (local.get $len)
+      (local.get $oflags) (local.get $rights) (local.get $rights) (i32.const 0) (i32.const 0)))
+  (func (export "opened") (result i32) (i32.load (i32.const 0)))
+  (func (export "path_create_directory") (param i32 i32 i32) (result i32)
+    (call $path_create_directory (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "path_unlink_file") (param i32 i32 i32) (result i32)
+    (call $path_unlink_file (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "path_remove_directory") (param i32 i32 i32) (result i32)
+    (call $path_remove_directory (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "path_rename") (param i32 i32 i32 i32 i32 i32) (result i32)
+    (call $path_rename (local.get 0) (local.get 1) (local.get 2)
+      (local.get 3) (local.get 4) (local.get 5)))
+  (func (export "fd_renumber") (param i32 i32) (result i32)
+    (call $fd_renumber (local.get 0) (local.get 1)))
+  (func (export "fd_close") (param i32) (result i32) (call $fd_close (local.get 0)))
+  (func (export "fd_prestat_get") (param i32) (result i32)
+    (call $fd_prestat_get (local.get 0) (i32.const 16)))
+  (func (export "clock_time_get") (result i32)
+    (call $clock_time_get (i32.const 0) (i64.const 1) (i32.const 16)))
+  (func (export "random_get") (result i32) (call $random_get (i32.const 16) (i32.const 8)))
+  (func (export "environ_sizes_get") (result i32)
+    (call $environ_sizes_get (i32.const 16) (i32.const 20))))"#;
+
+struct Guest {
+    instance: InstanceToken,
+    denials: Rc<RefCell<Vec<(String, DenialReason)>>>,
+    ro: ScratchFs,
+    rw: ScratchFs,
+}
+
+/// Instantiates `GUEST` with preopens "/ro" of `ro_rights` and "/rw" of all
+/// rights, both containing "file" and "dir", and with `configure` applied.
+fn guest<F: FnOnce(&mut WasiConfig)>(ro_rights: DirRights, configure: F) -> Guest {
+    let (ro, rw) = (ScratchFs::new().unwrap(), ScratchFs::new().unwrap());
+    for fs in &[&ro, &rw] {
+        fs.write_file("file", "contents").unwrap();
+        fs.create_dir("dir").unwrap();
+    }
+    let denials = Rc::new(RefCell::new(Vec::new()));
+    let mut wasi = WasiConfig::new();
+    let audited = denials.clone();
+    wasi.preopen_scratch_with_rights("/ro", &ro, ro_rights)
+        .preopen_scratch("/rw", &rw)
+        .audit(move |denial| {
+            audited
+                .borrow_mut()
+                .push((denial.function.to_owned(), denial.reason))
+        });
+    configure(&mut wasi);
+    let instance = instantiate_reactor(GUEST.as_bytes(), &mut wasi).unwrap();
+    Guest {
+        instance,
+        denials,
+        ro,
+        rw,
+    }
+}
+
+impl Guest {
+    fn path_open(&self, fd: i32, path: (i32, i32), oflags: i32, rights: i64) -> i32 {
+        let f = self.instance.get_typed_func::<(i32, i32, i32, i32, i64), i32>("path_open");
+        f.unwrap().call((fd, path.0, path.1, oflags, rights)).unwrap()
+    }
+
+    fn opened(&self) -> i32 {
+        let f = self.instance.get_typed_func::<(), i32>("opened");
+        f.unwrap().call(()).unwrap()
+    }
+
+    fn path_call(&self, name: &str, fd: i32, path: (i32, i32)) -> i32 {
+        let f = self.instance.get_typed_func::<(i32, i32, i32), i32>(name);
+        f.unwrap().call((fd, path.0, path.1)).unwrap()
+    }
+
+    fn path_rename(&self, fd: i32, path: (i32, i32), new_fd: i32, new_path: (i32, i32)) -> i32 {
+        let f = self.instance.get_typed_func::<(i32, i32, i32, i32, i32, i32), i32>("path_rename");
+        f.unwrap().call((fd, path.0, path.1, new_fd, new_path.0, new_path.1)).unwrap()
+    }
+
+    fn fd_call(&self, name: &str, fd: i32) -> i32 {
+        let f = self.instance.get_typed_func::<(i32,), i32>(name);
+        f.unwrap().call((fd,)).unwrap()
+    }
+
+    fn fd_renumber(&self, from: i32, to: i32) -> i32 {
+        let f = self.instance.get_typed_func::<(i32, i32), i32>("fd_renumber");
+        f.unwrap().call((from, to)).unwrap()
+    }
+
+    fn call(&self, name: &str) -> i32 {
+        let f = self.instance.get_typed_func::<(), i32>(name);
+        f.unwrap().call(()).unwrap()
+    }
+
+    fn denials(&self) -> Vec<(String, DenialReason)> {
+        self.denials.borrow().clone()
+    }
+}
+
+fn denied(function: &str, reason: DenialReason) -> Vec<(String, DenialReason)> {
+    vec![(function.to_owned(), reason)]
+}
+
+#[test]
+fn denied_clocks() {
+    let guest = guest(DirRights::all(), |wasi| {
+        wasi.deny(WasiCategory::Clocks);
+    });
+    assert_eq!(guest.call("clock_time_get"), ENOTCAPABLE);
+    assert_eq!(guest.call("random_get"), ESUCCESS);
+    let reason = DenialReason::Category(WasiCategory::Clocks);
+    assert_eq!(guest.denials(), denied("clock_time_get", reason));
+}
+
+#[test]
+fn denied_clocks_override_virtual_clock() {
+    let guest = guest(DirRights::all(), |wasi| {
+        wasi.clock(VirtualClock::new(0, 1)).deny(WasiCategory::Clocks);
+    });
+    assert_eq!(guest.call("clock_time_get"), ENOTCAPABLE);
+}
+
+#[test]
+fn denied_random() {
+    let guest = guest(DirRights::all(), |wasi| {
+        wasi.deny(WasiCategory::Random);
+    });
+    assert_eq!(guest.call("random_get"), ENOTCAPABLE);
+    assert_eq!(guest.call("clock_time_get"), ESUCCESS);
+    let reason = DenialReason::Category(WasiCategory::Random);
+    assert_eq!(guest.denials(), denied("random_get", reason));
+}
+
+#[test]
+fn denied_environment() {
+    let guest = guest(DirRights::all(), |wasi| {
+        wasi.deny(WasiCategory::Environment);
+    });
+    assert_eq!(guest.call("environ_sizes_get"), ENOTCAPABLE);
+    let reason = DenialReason::Category(WasiCategory::Environment);
+    assert_eq!(guest.denials(), denied("environ_sizes_get", reason));
+}
+
+#[test]
+fn denied_filesystem() {
+    let guest = guest(DirRights::all(), |wasi| {
+        wasi.deny(WasiCategory::Filesystem);
+    });
+    // No preopens are visible rather than failing the guest startup.
+    assert_eq!(guest.fd_call("fd_prestat_get", RO_FD), EBADF);
+    assert_eq!(guest.path_open(RW_FD, FILE, 0, RIGHT_FD_READ), ENOTCAPABLE);
+    assert_eq!(guest.path_call("path_create_directory", RW_FD, NEW), ENOTCAPABLE);
+    assert!(!guest.rw.exists("new"));
+    let reason = DenialReason::Category(WasiCategory::Filesystem);
+    assert_eq!(guest.denials()[0], (String::from("fd_prestat_get"), reason));
+}
+
+#[test]
+fn read_only_preopen() {
+    let guest = guest(DirRights::read_only(), |_| ());
+    let reason = DenialReason::Rights { fd: RO_FD as u32 };
+
+    assert_eq!(guest.path_open(RO_FD, FILE, 0, RIGHT_FD_READ), ESUCCESS);
+    assert_eq!(guest.path_open(RO_FD, FILE, 0, RIGHT_FD_WRITE), ENOTCAPABLE);
+    assert_eq!(guest.path_open(RO_FD, FILE, OFLAGS_TRUNC, RIGHT_FD_READ), ENOTCAPABLE);
+    assert_eq!(guest.path_open(RO_FD, NEW, OFLAGS_CREAT, RIGHT_FD_READ), ENOTCAPABLE);
+    assert_eq!(guest.path_call("path_create_directory", RO_FD, NEW), ENOTCAPABLE);
+    assert_eq!(guest.path_call("path_unlink_file", RO_FD, FILE), ENOTCAPABLE);
+    assert_eq!(guest.path_call("path_remove_directory", RO_FD, DIR), ENOTCAPABLE);
+    assert_eq!(guest.ro.read_dir("/").unwrap(), vec!["dir", "file"]);
+    assert_eq!(guest.ro.read_file("file").unwrap(), b"contents");
+    assert_eq!(guest.denials().len(), 6);
+    assert!(guest.denials().iter().all(|(_, r)| *r == reason));
+
+    // The other preopen is not restricted.
+    assert_eq!(guest.path_open(RW_FD, FILE, 0, RIGHT_FD_WRITE), ESUCCESS);
+    assert_eq!(guest.path_call("path_create_directory", RW_FD, NEW), ESUCCESS);
+    assert!(guest.rw.exists("new"));
+}
+
+#[test]
+fn read_only_applies_to_opened_directories() {
+    let guest = guest(DirRights::read_only(), |_| ());
+    assert_eq!(guest.path_open(RO_FD, DIR, OFLAGS_DIRECTORY, RIGHTS_DIRECTORY), ESUCCESS);
+    let dir = guest.opened();
+    assert_eq!(guest.path_call("path_create_directory", dir, NEW), ENOTCAPABLE);
+    assert!(!guest.ro.exists("dir/new"));
+}
+
+#[test]
+fn no_create_preopen() {
+    let guest = guest(DirRights::all().no_create(), |_| ());
+    assert_eq!(guest.path_call("path_create_directory", RO_FD, NEW), ENOTCAPABLE);
+    assert_eq!(guest.path_open(RO_FD, NEW, OFLAGS_CREAT, RIGHT_FD_WRITE), ENOTCAPABLE);
+    assert_eq!(guest.path_rename(RW_FD, FILE, RO_FD, NEW), ENOTCAPABLE);
+    assert!(!guest.ro.exists("new"));
+    // Existing files stay writable and removable.
+    assert_eq!(guest.path_open(RO_FD, FILE, OFLAGS_TRUNC, RIGHT_FD_WRITE), ESUCCESS);
+    assert_eq!(guest.path_call("path_unlink_file", RO_FD, FILE), ESUCCESS);
+    assert!(!guest.ro.exists("file"));
+}
+
+#[test]
+fn no_delete_preopen() {
+    let guest = guest(DirRights::all().no_delete(), |_| ());
+    assert_eq!(guest.path_call("path_unlink_file", RO_FD, FILE), ENOTCAPABLE);
+    assert_eq!(guest.path_call("path_remove_directory", RO_FD, DIR), ENOTCAPABLE);
+    assert_eq!(guest.path_rename(RO_FD, FILE, RO_FD, NEW), ENOTCAPABLE);
+    assert!(guest.ro.exists("file"));
+    assert!(guest.ro.exists("dir"));
+    // Creating is still allowed.
+    assert_eq!(guest.path_call("path_create_directory", RO_FD, NEW), ESUCCESS);
+    assert!(guest.ro.exists("new"));
+}
+
+#[test]
+fn rights_follow_renumbered_descriptor() {
+    let guest = guest(DirRights::read_only(), |_| ());
+    // The unrestricted preopen replaces the read-only one.
+    assert_eq!(guest.fd_renumber(RW_FD, RO_FD), ESUCCESS);
+    assert_eq!(guest.path_call("path_create_directory", RO_FD, NEW), ESUCCESS);
+    assert!(guest.rw.exists("new"));
+    assert!(guest.denials().is_empty());
+}
+
+#[test]
+fn restricted_rights_move_with_renumber() {
+    let guest = guest(DirRights::read_only(), |_| ());
+    assert_eq!(guest.fd_renumber(RO_FD, RW_FD), ESUCCESS);
+    assert_eq!(guest.path_call("path_create_directory", RW_FD, NEW), ENOTCAPABLE);
+    assert!(!guest.ro.exists("new"));
+    assert!(!guest.rw.exists("new"));
+}
+
+#[test]
+fn closed_descriptor_rights_are_dropped() {
+    let guest = guest(DirRights::read_only(), |_| ());
+    assert_eq!(guest.fd_call("fd_close", RO_FD), ESUCCESS);
+    // The directory opened through the unrestricted preopen may reuse the
+    // number of the closed read-only one.
+    assert_eq!(guest.path_open(RW_FD, DIR, OFLAGS_DIRECTORY, RIGHTS_DIRECTORY), ESUCCESS);
+    let dir = guest.opened();
+    assert_eq!(guest.path_call("path_create_directory", dir, NEW), ESUCCESS);
+    assert!(guest.rw.exists("dir/new"));
+}
@@ -0,0 +1,38 @@
+use std::collections::HashMap;
+use wasmtime_embed::{instantiate, validate, wat2wasm, Config, ValidationError, WatError};
+
+#[test]
+fn text_module_is_instantiated() {
+    let wat = r#"(module (func (export "answer") (result i32) i32.const 42))"#;
+    let instance = instantiate(wat.as_bytes(), HashMap::new()).unwrap();
+    let answer = instance.get_typed_func::<(), i32>("answer").unwrap();
+    assert_eq!(answer.call(()).unwrap(), 42);
+}
+
+#[test]
+fn text_parse_error_has_position() {
+    let error = wat2wasm("(module\n  (func (result i32) i32.const))").unwrap_err();
+    assert_eq!(error.line, 2);
+    assert!(error.column > 0);
+}
+
+#[test]
+fn binary_decode_error_is_not_a_text_error() {
+    let binaries: &[&[u8]] = &[
+        b"\0as",
+        b"\0asm\x02\0\0\0",
+        b"\0asm\x01\0\0\0\x01\xff",
+        b"\0\0\0\0",
+    ];
+    for &binary in binaries {
+        let error = instantiate(binary, HashMap::new()).err().expect("error");
+        assert!(error.downcast_ref::<WatError>().is_none(), "{:?}", binary);
+        assert!(error.downcast_ref::<ValidationError>().is_some(), "{:?}", binary);
+    }
+}
+
+#[test]
+fn binary_from_text_validates() {
+    let binary = wat2wasm("(module (memory 1))").unwrap();
+    assert!(validate(&binary, &Config::new()).is_ok());
+}